@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+    ALTER COLUMN role DROP DEFAULT,
+    ALTER COLUMN role TYPE VARCHAR USING role::text;
+
+ALTER TABLE users RENAME COLUMN role TO permissions;
+
+DROP TYPE user_role;
\ No newline at end of file
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TYPE user_role AS ENUM ('listener', 'uploader', 'admin');
+
+ALTER TABLE users RENAME COLUMN permissions TO role;
+
+-- Existing free-form values are mapped to the closest role, anything unknown becomes a listener
+ALTER TABLE users
+    ALTER COLUMN role TYPE user_role USING (
+        CASE lower(trim(role))
+            WHEN 'admin' THEN 'admin'
+            WHEN 'uploader' THEN 'uploader'
+            ELSE 'listener'
+        END
+    )::user_role,
+    ALTER COLUMN role SET DEFAULT 'listener';
\ No newline at end of file
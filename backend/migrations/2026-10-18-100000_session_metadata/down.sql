@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS session_expires_at_idx;
+DROP INDEX IF EXISTS session_user_id_idx;
+
+ALTER TABLE session
+    DROP COLUMN ip_address,
+    DROP COLUMN user_agent,
+    DROP COLUMN last_seen,
+    DROP COLUMN created_at;
\ No newline at end of file
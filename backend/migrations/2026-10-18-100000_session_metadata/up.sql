@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE session
+    ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT now(),
+    ADD COLUMN last_seen TIMESTAMP NOT NULL DEFAULT now(),
+    ADD COLUMN user_agent VARCHAR,
+    ADD COLUMN ip_address VARCHAR;
+
+CREATE INDEX IF NOT EXISTS session_user_id_idx ON session (user_id);
+CREATE INDEX IF NOT EXISTS session_expires_at_idx ON session (expires_at);
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE songs
+    DROP COLUMN bitrate_kbps,
+    DROP COLUMN codec;
+
+DROP TYPE segment_codec;
\ No newline at end of file
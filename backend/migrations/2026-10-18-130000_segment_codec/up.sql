@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TYPE segment_codec AS ENUM ('wav', 'mp3');
+
+-- Songs added before encoding was introduced have raw WAV samples
+ALTER TABLE songs
+    ADD COLUMN codec segment_codec NOT NULL DEFAULT 'wav',
+    ADD COLUMN bitrate_kbps INTEGER;
\ No newline at end of file
@@ -0,0 +1,17 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE songs
+    ADD COLUMN codec segment_codec NOT NULL DEFAULT 'wav',
+    ADD COLUMN bitrate_kbps INTEGER;
+
+-- Songs go back to a single rendition, keeping the best one.
+-- Samples of renditions added since are stored under `{song_id}/{name}/` and have to be moved by hand.
+UPDATE songs
+SET codec = best.codec, bitrate_kbps = best.bitrate_kbps
+FROM (
+    SELECT DISTINCT ON (song_id) song_id, codec, bitrate_kbps
+    FROM song_renditions
+    ORDER BY song_id, (codec = 'wav') DESC, bitrate_kbps DESC NULLS LAST
+) AS best
+WHERE songs.id = best.song_id;
+
+DROP TABLE song_renditions;
\ No newline at end of file
@@ -0,0 +1,28 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS song_renditions (
+    id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+    song_id uuid NOT NULL,
+    name VARCHAR NOT NULL,
+    codec segment_codec NOT NULL,
+    bitrate_kbps INT,
+    key_prefix VARCHAR NOT NULL,
+    CONSTRAINT fk_song_id
+        FOREIGN KEY (song_id)
+            REFERENCES songs(id)
+            ON DELETE CASCADE,
+    CONSTRAINT unique_song_rendition_name UNIQUE (song_id, name)
+);
+
+-- Existing songs keep their samples where they are, as their only rendition
+INSERT INTO song_renditions (song_id, name, codec, bitrate_kbps, key_prefix)
+SELECT
+    id,
+    CASE codec WHEN 'wav' THEN 'lossless' ELSE codec::text || '_' || bitrate_kbps END,
+    codec,
+    bitrate_kbps,
+    id::text
+FROM songs;
+
+ALTER TABLE songs
+    DROP COLUMN codec,
+    DROP COLUMN bitrate_kbps;
\ No newline at end of file
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE songs DROP COLUMN lossless;
+
+-- Postgres can't drop a value from an enum, so the type is rebuilt without it.
+-- The samples of FLAC renditions are left in storage.
+DELETE FROM song_renditions WHERE codec = 'flac';
+
+ALTER TYPE segment_codec RENAME TO segment_codec_old;
+CREATE TYPE segment_codec AS ENUM ('wav', 'mp3');
+ALTER TABLE song_renditions
+    ALTER COLUMN codec TYPE segment_codec USING codec::text::segment_codec;
+DROP TYPE segment_codec_old;
\ No newline at end of file
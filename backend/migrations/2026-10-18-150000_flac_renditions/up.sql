@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TYPE segment_codec ADD VALUE IF NOT EXISTS 'flac';
+
+ALTER TABLE songs ADD COLUMN lossless BOOLEAN NOT NULL DEFAULT false;
+
+-- Songs with a WAV rendition are already available losslessly
+UPDATE songs
+SET lossless = true
+WHERE EXISTS (
+    SELECT 1 FROM song_renditions
+    WHERE song_renditions.song_id = songs.id AND song_renditions.codec = 'wav'
+);
\ No newline at end of file
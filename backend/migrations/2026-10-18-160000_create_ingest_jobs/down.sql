@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS ingest_jobs;
+
+-- Songs that never finished processing have no samples
+DELETE FROM songs WHERE NOT ready;
+ALTER TABLE songs DROP COLUMN ready;
+
+DROP TYPE job_status;
\ No newline at end of file
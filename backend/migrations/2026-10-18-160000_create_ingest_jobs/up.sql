@@ -0,0 +1,25 @@
+-- Your SQL goes here
+CREATE TYPE job_status AS ENUM ('queued', 'processing', 'failed', 'ready');
+
+-- Songs are added before their samples are made, and only listed once they're ready
+ALTER TABLE songs ADD COLUMN ready BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE songs ALTER COLUMN ready SET DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS ingest_jobs (
+    id uuid DEFAULT gen_random_uuid() PRIMARY KEY,
+    song_id uuid NOT NULL,
+    status job_status NOT NULL DEFAULT 'queued',
+    progress INTEGER NOT NULL DEFAULT 0,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error VARCHAR,
+    upload_path VARCHAR NOT NULL,
+    run_after TIMESTAMP NOT NULL DEFAULT now(),
+    created_at TIMESTAMP NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP NOT NULL DEFAULT now(),
+    CONSTRAINT fk_song_id
+        FOREIGN KEY (song_id)
+            REFERENCES songs(id)
+            ON DELETE CASCADE
+);
+
+CREATE INDEX ingest_jobs_status_idx ON ingest_jobs (status, run_after);
\ No newline at end of file
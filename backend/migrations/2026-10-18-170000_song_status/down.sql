@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE songs ADD COLUMN ready BOOLEAN NOT NULL DEFAULT false;
+UPDATE songs SET ready = true WHERE status = 'ready';
+ALTER TABLE songs DROP COLUMN status;
+
+DROP TYPE song_status;
\ No newline at end of file
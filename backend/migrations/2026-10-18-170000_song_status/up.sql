@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Songs are staged while their objects are uploaded and marked as deleting while they are removed,
+-- and are only part of the catalog while they're ready
+CREATE TYPE song_status AS ENUM ('staged', 'ready', 'deleting');
+
+ALTER TABLE songs ADD COLUMN status song_status NOT NULL DEFAULT 'staged';
+UPDATE songs SET status = 'ready' WHERE ready;
+ALTER TABLE songs DROP COLUMN ready;
\ No newline at end of file
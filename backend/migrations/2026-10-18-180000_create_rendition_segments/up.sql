@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- The size of each stored segment, so byte ranges of a whole song can be found without fetching its segments
+CREATE TABLE IF NOT EXISTS rendition_segments (
+    rendition_id uuid NOT NULL,
+    segment_number INTEGER NOT NULL,
+    byte_length INTEGER NOT NULL,
+    PRIMARY KEY (rendition_id, segment_number),
+    CONSTRAINT fk_rendition_id
+        FOREIGN KEY (rendition_id)
+            REFERENCES song_renditions(id)
+            ON DELETE CASCADE
+);
\ No newline at end of file
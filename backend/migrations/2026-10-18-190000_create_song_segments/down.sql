@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS song_segments;
+
+ALTER TABLE songs DROP COLUMN IF EXISTS sample_rate;
\ No newline at end of file
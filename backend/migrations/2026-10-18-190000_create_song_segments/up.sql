@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Where each segment of a song starts and how many frames it has, so positions in the song can be found exactly.
+-- Songs added before this have no segments recorded and no sample rate
+ALTER TABLE songs ADD COLUMN IF NOT EXISTS sample_rate INTEGER;
+
+CREATE TABLE IF NOT EXISTS song_segments (
+    song_id uuid NOT NULL,
+    segment_number INTEGER NOT NULL,
+    start_frame BIGINT NOT NULL,
+    frames INTEGER NOT NULL,
+    PRIMARY KEY (song_id, segment_number),
+    CONSTRAINT fk_song_id
+        FOREIGN KEY (song_id)
+            REFERENCES songs(id)
+            ON DELETE CASCADE
+);
\ No newline at end of file
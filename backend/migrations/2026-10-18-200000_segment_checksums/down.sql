@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE rendition_segments DROP COLUMN IF EXISTS checksum;
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- The SHA-256 of each stored segment, recorded when songs are added
+ALTER TABLE rendition_segments ADD COLUMN IF NOT EXISTS checksum VARCHAR;
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE songs ADD COLUMN duration INT NOT NULL DEFAULT 0;
+UPDATE songs SET duration = duration_ms / 1000;
+ALTER TABLE songs ALTER COLUMN duration DROP DEFAULT;
+
+ALTER TABLE songs DROP COLUMN duration_ms;
\ No newline at end of file
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- Durations are stored in milliseconds, as whole seconds cut off the end of the last second.
+-- Songs whose segments were recorded get their exact length, rounded up, and others keep their length in whole seconds
+ALTER TABLE songs ADD COLUMN duration_ms INTEGER NOT NULL DEFAULT 0;
+UPDATE songs SET duration_ms = duration * 1000;
+UPDATE songs SET duration_ms = CEIL(recorded.frames * 1000.0 / songs.sample_rate)
+FROM (SELECT song_id, SUM(frames) AS frames FROM song_segments GROUP BY song_id) AS recorded
+WHERE recorded.song_id = songs.id AND songs.sample_rate > 0;
+
+ALTER TABLE songs ALTER COLUMN duration_ms DROP DEFAULT;
+ALTER TABLE songs DROP COLUMN duration;
\ No newline at end of file
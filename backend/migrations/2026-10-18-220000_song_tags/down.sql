@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE songs
+    DROP COLUMN IF EXISTS album_artist,
+    DROP COLUMN IF EXISTS track_number,
+    DROP COLUMN IF EXISTS disc_number,
+    DROP COLUMN IF EXISTS year,
+    DROP COLUMN IF EXISTS genre,
+    DROP COLUMN IF EXISTS isrc;
\ No newline at end of file
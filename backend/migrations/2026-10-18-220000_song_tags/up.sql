@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- Details read from the tags embedded in uploads, or given with them
+ALTER TABLE songs
+    ADD COLUMN IF NOT EXISTS album_artist VARCHAR,
+    ADD COLUMN IF NOT EXISTS track_number INTEGER,
+    ADD COLUMN IF NOT EXISTS disc_number INTEGER,
+    ADD COLUMN IF NOT EXISTS year INTEGER,
+    ADD COLUMN IF NOT EXISTS genre VARCHAR,
+    ADD COLUMN IF NOT EXISTS isrc VARCHAR;
\ No newline at end of file
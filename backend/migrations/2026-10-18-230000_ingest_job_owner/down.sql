@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE ingest_jobs DROP COLUMN IF EXISTS created_by;
\ No newline at end of file
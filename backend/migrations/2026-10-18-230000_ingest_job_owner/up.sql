@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Who uploaded the song, so only they and admins can follow the job
+ALTER TABLE ingest_jobs
+    ADD COLUMN IF NOT EXISTS created_by uuid REFERENCES users(id) ON DELETE SET NULL;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+    ADD COLUMN failed_login_attempts INT NOT NULL DEFAULT 0,
+    ADD COLUMN locked_until TIMESTAMP;
+
+UPDATE users SET failed_login_attempts = login_failures.failed_attempts, locked_until = login_failures.locked_until
+    FROM login_failures WHERE login_failures.username = users.username;
+
+DROP TABLE IF EXISTS login_failures;
\ No newline at end of file
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Failed logins are counted per attempted username, whether or not the user exists
+CREATE TABLE IF NOT EXISTS login_failures (
+    username VARCHAR PRIMARY KEY,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    locked_until TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+INSERT INTO login_failures (username, failed_attempts, locked_until)
+    SELECT username, failed_login_attempts, locked_until FROM users
+    WHERE failed_login_attempts > 0 OR locked_until IS NOT NULL;
+
+ALTER TABLE users
+    DROP COLUMN failed_login_attempts,
+    DROP COLUMN locked_until;
\ No newline at end of file
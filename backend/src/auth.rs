@@ -1,37 +1,89 @@
+use std::{
+    env,
+    sync::OnceLock
+};
+
 use argon2::{
+    Algorithm,
     Argon2,
-    PasswordHasher
-};
-use diesel::{
-    prelude::*,
-    result::Error
+    Params,
+    PasswordHash,
+    PasswordHasher,
+    PasswordVerifier,
+    Version
 };
+use diesel::prelude::*;
+use dotenvy::dotenv;
 use password_hash::{
     rand_core::OsRng,
     SaltString
 };
 use uuid::Uuid;
 
-use crate::models::*;
+use crate::{config::env_or, error::AppError, models::*, roles::Role};
+
+/// Builds the Argon2id hasher used for new password hashes.
+///
+/// The cost can be tuned with `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`,
+/// which default to the argon2 crate's recommended parameters.
+pub fn password_hasher() -> Argon2<'static> {
+    dotenv().ok();
+
+    let memory = env_or("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST);
+    let iterations = env_or("ARGON2_ITERATIONS", Params::DEFAULT_T_COST);
+    let parallelism = env_or("ARGON2_PARALLELISM", Params::DEFAULT_P_COST);
+
+    let params = match Params::new(memory, iterations, parallelism, None) {
+        Ok(params) => params,
+        Err(err) => {
+            println!("Invalid Argon2 parameters, using the defaults: {}", err);
+            Params::default()
+        }
+    };
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+/// Whether a stored hash was made with a different algorithm or cost than the hasher uses now.
+fn needs_rehash(hash: &PasswordHash, hasher: &Argon2) -> bool {
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into()) {
+        return true;
+    }
+    let params = match Params::try_from(hash) {
+        Ok(params) => params,
+        Err(_) => return true
+    };
+    let current = hasher.params();
+    params.m_cost() != current.m_cost()
+        || params.t_cost() != current.t_cost()
+        || params.p_cost() != current.p_cost()
+}
+
+/// Hashes a password with the current Argon2 parameters, returning the PHC string.
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    match password_hasher().hash_password(password.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(_) => Err(AppError::Internal("Failed to hash password!".to_string()))
+    }
+}
 
-pub fn create_user(conn: &mut PgConnection, arg_username: &str, password: &str) -> Result<Users, &'static str> {
+pub fn create_user(conn: &mut PgConnection, arg_username: &str, password: &str) -> Result<Users, AppError> {
     use crate::schema::users;
     use crate::schema::users::dsl::*;
 
     // Checking if the user already exists
-    let response = users.filter(username.eq(arg_username)).select(Users::as_select()).load(conn).expect("Error selecting from database!");
-    if response.len() != 0 {
-        return Err("Username already exists!");
+    let response = users.filter(username.eq(arg_username)).select(Users::as_select()).load(conn)?;
+    if !response.is_empty() {
+        return Err(AppError::Conflict("Username already exists!".to_string()));
     }
 
     // Hashing the password
-    let salt = SaltString::generate(&mut OsRng);
-    let argon2 = Argon2::default();
-    let password_hash2 = argon2.hash_password(password.as_bytes(), &salt).expect("Failed to hash password!").to_string();
+    let password_hash2 = hash_password(password)?;
 
     // Creating the user
     let new_user = NewUser {
         username: arg_username,
+        role: Role::Listener,
         password_hash: &password_hash2,
     };
     
@@ -39,91 +91,260 @@ pub fn create_user(conn: &mut PgConnection, arg_username: &str, password: &str)
     let result = diesel::insert_into(users::table)
         .values(&new_user)
         .returning(Users::as_returning())
-        .get_result(conn)
-        .expect("Error saving new user");
+        .get_result(conn)?;
+
+    Ok(result)
+}
+
+/// A hash of a random password made by the current hasher, which passwords for unknown usernames are checked against
+/// so that logging in as someone who doesn't exist takes as long as a wrong password.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    DUMMY_HASH.get_or_init(|| {
+        let password = SaltString::generate(&mut OsRng);
+        hash_password(password.as_str()).unwrap_or_default()
+    })
+}
 
-    return Ok(result);
+/// Makes the dummy hash ahead of time, so the first login as an unknown username isn't slower than the rest.
+pub fn prepare_dummy_hash() {
+    dummy_hash();
 }
 
-pub fn verify_user(conn: &mut PgConnection, arg_username: &str, arg_password: &str) -> Result<Uuid, &'static str> {
+pub fn verify_user(conn: &mut PgConnection, arg_username: &str, arg_password: &str) -> Result<Uuid, AppError> {
     use crate::schema::users::dsl::*;
 
-    let response = users.filter(username.eq(arg_username)).select(Users::as_select()).load(conn).expect("Error loading users");
-    let user = response.get(0);
+    let incorrect = || AppError::Unauthorized("Username or password is incorrect!".to_string());
+
+    let response = users.filter(username.eq(arg_username)).select(Users::as_select()).load(conn)?;
+    let user = response.first();
     let user = match user {
         Some(user) => user,
-        None => return Err("Username or password is incorrect!")
+        None => {
+            if let Ok(parsed_hash) = PasswordHash::new(dummy_hash()) {
+                let _ = password_hasher().verify_password(arg_password.as_bytes(), &parsed_hash);
+            }
+            return Err(incorrect());
+        }
     };
     
-    let encoded_hash = user.password_hash.clone();
-    let vals = encoded_hash.split("$").collect::<Vec<&str>>();
-    let salt = SaltString::from_b64(vals[4]).expect("Failed to decode salt");
-    let argon2 = Argon2::default();
-    let password_hashed = argon2.hash_password(arg_password.as_bytes(), &salt).expect("Failed to hash password!").to_string();
-    if password_hashed == encoded_hash {
-        return Ok(user.id);
+    // The stored PHC string carries its own algorithm, parameters and salt, which the verifier uses
+    let parsed_hash = match PasswordHash::new(&user.password_hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return Err(incorrect())
+    };
+    let argon2 = password_hasher();
+    if argon2.verify_password(arg_password.as_bytes(), &parsed_hash).is_err() {
+        return Err(incorrect());
+    }
+
+    // Upgrade the hash to the current parameters now that we know the password
+    if needs_rehash(&parsed_hash, &argon2) {
+        let salt = SaltString::generate(&mut OsRng);
+        match argon2.hash_password(arg_password.as_bytes(), &salt) {
+            Ok(new_hash) => {
+                let result = diesel::update(users.filter(id.eq(user.id)))
+                    .set(password_hash.eq(new_hash.to_string()))
+                    .execute(conn);
+                if let Err(err) = result {
+                    println!("Error upgrading password hash: {:?}", err);
+                }
+            },
+            Err(err) => println!("Error upgrading password hash: {:?}", err)
+        }
     }
-    return Err("Username or password is incorrect!");
+
+    Ok(user.id)
 }
 
-pub fn create_session(conn: &mut PgConnection, arg_user_id: &uuid::Uuid) -> Result<Session, &'static str> {
+/// How long a session lasts, set by `SESSION_LIFETIME_DAYS` (defaults to 30 days).
+pub fn session_lifetime() -> chrono::Duration {
+    dotenv().ok();
+    chrono::Duration::days(env_or("SESSION_LIFETIME_DAYS", 30))
+}
+
+/// Whether using a session pushes its expiry back, enabled by setting `SESSION_SLIDING_EXPIRY` to `true`.
+pub fn sliding_expiry() -> bool {
+    dotenv().ok();
+    env::var("SESSION_SLIDING_EXPIRY").map(|value| value == "true").unwrap_or(false)
+}
+
+pub fn create_session(conn: &mut PgConnection, arg_user_id: &uuid::Uuid, user_agent: Option<&str>, ip_address: Option<&str>) -> Result<Session, AppError> {
     use crate::schema::session;
 
     let new_session = NewSession {
         user_id: arg_user_id,
-        expires_at: chrono::Utc::now().naive_utc() + chrono::Duration::days(30), // 30 days till expiration
+        expires_at: chrono::Utc::now().naive_utc() + session_lifetime(),
+        user_agent,
+        ip_address,
     };
 
     let result = diesel::insert_into(session::table)
         .values(&new_session)
         .returning(Session::as_returning())
-        .get_result(conn)
-        .expect("Error saving new session");
+        .get_result(conn)?;
 
-    return Ok(result);
+    Ok(result)
 }
 
-pub fn valid_session(conn: &mut PgConnection, arg_session_id: &uuid::Uuid) -> bool {
+pub fn valid_session(conn: &mut PgConnection, arg_session_id: &uuid::Uuid) -> Result<bool, AppError> {
     use crate::schema::session::dsl::*;
 
-    let response = session.filter(id.eq(arg_session_id)).select(Session::as_select()).load(conn).expect("Error loading sessions");
-    let session_var = response.get(0);
+    let response = session.filter(id.eq(arg_session_id)).select(Session::as_select()).load(conn)?;
+    let session_var = response.first();
     let session_var = match session_var {
         Some(session_var) => session_var,
-        None => return false
+        None => return Ok(false)
     };
 
     if session_var.expires_at < chrono::Utc::now().naive_utc() {
-        return false;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+pub fn invalidate_session(conn: &mut PgConnection, arg_session_id: &uuid::Uuid) -> Result<usize, AppError> {
+    use crate::schema::session::dsl::*;
+
+    Ok(diesel::delete(session.filter(id.eq(arg_session_id))).execute(conn)?)
+}
+
+/// Records that a session has been used, extending its expiry if sliding expiration is enabled.
+///
+/// Sessions seen within the last minute are left alone to avoid a write on every request.
+pub fn touch_session(conn: &mut PgConnection, arg_session_id: &uuid::Uuid) -> Result<usize, AppError> {
+    use crate::schema::session::dsl::*;
+
+    let now = chrono::Utc::now().naive_utc();
+    let target = session
+        .filter(id.eq(arg_session_id))
+        .filter(last_seen.lt(now - chrono::Duration::minutes(1)));
+
+    let result = if sliding_expiry() {
+        diesel::update(target)
+            .set((last_seen.eq(now), expires_at.eq(now + session_lifetime())))
+            .execute(conn)?
     }
+    else {
+        diesel::update(target)
+            .set(last_seen.eq(now))
+            .execute(conn)?
+    };
+    Ok(result)
+}
+
+/// Gets the sessions of a user that haven't expired, most recently used first.
+pub fn get_user_sessions(conn: &mut PgConnection, arg_user_id: &uuid::Uuid) -> Result<Vec<Session>, AppError> {
+    use crate::schema::session::dsl::*;
+
+    let response = session
+        .filter(user_id.eq(arg_user_id))
+        .filter(expires_at.gt(chrono::Utc::now().naive_utc()))
+        .order(last_seen.desc())
+        .select(Session::as_select())
+        .load(conn)?;
+    Ok(response)
+}
+
+/// Deletes one of a user's sessions, failing with [`AppError::NotFound`] if the user has no such session.
+pub fn revoke_user_session(conn: &mut PgConnection, arg_user_id: &uuid::Uuid, arg_session_id: &uuid::Uuid) -> Result<(), AppError> {
+    use crate::schema::session::dsl::*;
+
+    let deleted = diesel::delete(session.filter(id.eq(arg_session_id)).filter(user_id.eq(arg_user_id))).execute(conn)?;
+    if deleted == 0 {
+        return Err(AppError::NotFound("Session not found".to_string()));
+    }
+    Ok(())
+}
 
-    return true;
+/// Deletes every session of a user except the one given.
+pub fn revoke_other_sessions(conn: &mut PgConnection, arg_user_id: &uuid::Uuid, current_session_id: &uuid::Uuid) -> Result<usize, AppError> {
+    use crate::schema::session::dsl::*;
+
+    Ok(diesel::delete(session.filter(user_id.eq(arg_user_id)).filter(id.ne(current_session_id))).execute(conn)?)
 }
 
-pub fn invalidate_session(conn: &mut PgConnection, arg_session_id: &uuid::Uuid) -> Result<usize, Error> {
+/// Deletes all expired sessions.
+pub fn purge_expired_sessions(conn: &mut PgConnection) -> Result<usize, AppError> {
     use crate::schema::session::dsl::*;
 
-    diesel::delete(session.filter(id.eq(arg_session_id))).execute(conn)
+    Ok(diesel::delete(session.filter(expires_at.le(chrono::Utc::now().naive_utc()))).execute(conn)?)
 }
 
-pub fn get_user(conn: &mut PgConnection, arg_session_id: &uuid::Uuid) -> Result<Users, &'static str> {
+/// Gets the user a session belongs to, failing with [`AppError::NotFound`] if the session doesn't exist or has expired.
+pub fn get_user(conn: &mut PgConnection, arg_session_id: &uuid::Uuid) -> Result<Users, AppError> {
     use crate::schema::{session, users};
 
     let resp = session::table
         .inner_join(users::table)
         .filter(session::id.eq(arg_session_id))
+        .filter(session::expires_at.gt(chrono::Utc::now().naive_utc()))
         .select((Session::as_select(), Users::as_select()))
-        .load::<(Session, Users)>(conn);
+        .load::<(Session, Users)>(conn)?;
 
-    let resp = match resp {
-        Ok(resp) => resp,
-        Err(_) => return Err("Error loading users")
-    };
-    let resp = resp.get(0);
+    let resp = resp.first();
     let resp = match resp {
         Some(resp) => resp,
-        None => return Err("Error getting user")
+        None => return Err(AppError::NotFound("Session not found".to_string()))
     };
     let user = resp.1.clone();
-    return Ok(user);
+    Ok(user)
+}
+
+pub fn get_users(conn: &mut PgConnection) -> Result<Vec<Users>, AppError> {
+    use crate::schema::users::dsl::*;
+
+    Ok(users.order(username.asc()).select(Users::as_select()).load(conn)?)
+}
+
+/// Changes the role of a user, failing with [`AppError::NotFound`] if the user doesn't exist.
+pub fn set_user_role(conn: &mut PgConnection, arg_user_id: &uuid::Uuid, new_role: Role) -> Result<Users, AppError> {
+    use crate::schema::users::dsl::*;
+
+    let result = diesel::update(users.filter(id.eq(arg_user_id)))
+        .set(role.eq(new_role))
+        .returning(Users::as_returning())
+        .get_result(conn)
+        .optional()?;
+    match result {
+        Some(result) => Ok(result),
+        None => Err(AppError::NotFound("User not found".to_string()))
+    }
+}
+
+pub fn get_user_by_username(conn: &mut PgConnection, arg_username: &str) -> Result<Option<Users>, AppError> {
+    use crate::schema::users::dsl::*;
+
+    Ok(users.filter(username.eq(arg_username)).select(Users::as_select()).first(conn).optional()?)
+}
+
+/// Changes a user's password after checking their current one.
+pub fn change_password(conn: &mut PgConnection, arg_user_id: &uuid::Uuid, current_password: &str, new_password: &str) -> Result<(), AppError> {
+    use crate::schema::users::dsl::*;
+
+    let incorrect = || AppError::Unauthorized("Current password is incorrect!".to_string());
+
+    let user = users.filter(id.eq(arg_user_id)).select(Users::as_select()).first(conn)?;
+
+    let parsed_hash = match PasswordHash::new(&user.password_hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return Err(incorrect())
+    };
+    if password_hasher().verify_password(current_password.as_bytes(), &parsed_hash).is_err() {
+        return Err(incorrect());
+    }
+
+    let new_hash = hash_password(new_password)?;
+    diesel::update(users.filter(id.eq(arg_user_id)))
+        .set(password_hash.eq(new_hash))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Deletes every session of a user.
+pub fn revoke_all_sessions(conn: &mut PgConnection, arg_user_id: &uuid::Uuid) -> Result<usize, AppError> {
+    use crate::schema::session::dsl::*;
+
+    Ok(diesel::delete(session.filter(user_id.eq(arg_user_id))).execute(conn)?)
 }
\ No newline at end of file
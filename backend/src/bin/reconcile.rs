@@ -0,0 +1,46 @@
+use std::{env, process};
+
+use backend::{
+    db::establish_pool,
+    reconcile::reconcile,
+    storage::storage_from_env
+};
+
+/// Checks the storage against the `songs` table, reporting orphaned and missing objects.
+///
+/// Run with `--fix` to delete the orphaned objects and finish deleting songs whose deletion failed.
+#[actix_web::main]
+async fn main() {
+    let fix = env::args().skip(1).any(|arg| arg == "--fix");
+    let pool = establish_pool();
+    let storage = storage_from_env();
+
+    let report = match reconcile(&pool, storage.as_ref(), fix).await {
+        Ok(report) => report,
+        Err(err) => {
+            println!("Error reconciling storage: {}", err);
+            process::exit(1);
+        }
+    };
+
+    for key in &report.orphaned {
+        println!("Orphaned: {}", key);
+    }
+    for key in &report.missing {
+        println!("Missing: {}", key);
+    }
+    for song_id in &report.abandoned {
+        println!("Abandoned upload: {}", song_id);
+    }
+    for song_id in &report.unfinished_deletions {
+        println!("Unfinished deletion: {}", song_id);
+    }
+    let action = match fix {
+        true => "deleted",
+        false => "found, run with --fix to delete them"
+    };
+    println!(
+        "{} orphaned objects {}, {} missing objects, {} abandoned uploads, {} unfinished deletions",
+        report.orphaned.len(), action, report.missing.len(), report.abandoned.len(), report.unfinished_deletions.len()
+    );
+}
\ No newline at end of file
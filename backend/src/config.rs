@@ -0,0 +1,12 @@
+use std::{
+    env,
+    str::FromStr
+};
+
+/// Reads an environment variable, falling back to the default if it is missing or invalid.
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
\ No newline at end of file
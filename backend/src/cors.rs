@@ -0,0 +1,42 @@
+use std::env;
+use actix_cors::Cors;
+use dotenvy::dotenv;
+
+/// Reads the origins allowed to make requests with credentials from `CORS_ALLOWED_ORIGINS`,
+/// a comma separated list of origins like `https://example.com` (defaults to `http://localhost:3000`).
+pub fn allowed_origins_from_env() -> Vec<String> {
+    dotenv().ok();
+    let origins = env::var("CORS_ALLOWED_ORIGINS").unwrap_or("http://localhost:3000".to_string());
+    let origins: Vec<String> = origins
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    if origins.is_empty() {
+        panic!("CORS_ALLOWED_ORIGINS must list at least one origin");
+    }
+    for origin in &origins {
+        if !is_origin(origin) {
+            panic!("Invalid origin in CORS_ALLOWED_ORIGINS: {}", origin);
+        }
+    }
+    origins
+}
+
+/// Only lets the given origins make requests, since they are sent with the session cookie.
+pub fn cors(origins: &[String]) -> Cors {
+    let cors = Cors::default()
+        .allowed_headers(vec!["Content-Type", "Authorization"])
+        .allow_any_method()
+        .supports_credentials();
+    origins.iter().fold(cors, |cors, origin| cors.allowed_origin(origin))
+}
+
+/// Whether the value is a scheme and host, with an optional port and nothing after it.
+fn is_origin(value: &str) -> bool {
+    let host = match value.strip_prefix("https://").or_else(|| value.strip_prefix("http://")) {
+        Some(host) => host,
+        None => return false
+    };
+    !host.is_empty() && !host.contains(['/', '*', '?', '#', '@'])
+}
\ No newline at end of file
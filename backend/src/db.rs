@@ -1,11 +1,49 @@
-use diesel::prelude::*;
+use std::{
+    env,
+    time::Duration
+};
+use actix_web::web;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool}
+};
 use dotenvy::dotenv;
-use std::env;
 
-pub fn establish_connection() -> PgConnection {
+use crate::{config::env_or, error::AppError};
+
+pub type DbPool = Pool<ConnectionManager<PgConnection>>;
+
+/// Creates the database connection pool.
+///
+/// The pool is created without connecting so the server can start while the
+/// database is down; requests made in the meantime fail with a 503.
+/// `DB_POOL_MAX_SIZE` (default 10) and `DB_POOL_TIMEOUT_SECS` (default 5) tune the pool.
+pub fn establish_pool() -> DbPool {
     dotenv().ok();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&database_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
+    let max_size = env_or("DB_POOL_MAX_SIZE", 10);
+    let timeout = env_or("DB_POOL_TIMEOUT_SECS", 5);
+
+    let manager = ConnectionManager::<PgConnection>::new(database_url);
+    Pool::builder()
+        .max_size(max_size)
+        .connection_timeout(Duration::from_secs(timeout))
+        .build_unchecked(manager)
+}
+
+/// Runs blocking database work on the thread pool with a connection from the pool,
+/// keeping it off the async executor.
+///
+/// Fails with [`AppError::Unavailable`] if no connection can be taken from the pool.
+pub async fn run<F, T>(pool: &DbPool, query: F) -> Result<T, AppError>
+where
+    F: FnOnce(&mut PgConnection) -> Result<T, AppError> + Send + 'static,
+    T: Send + 'static
+{
+    let pool = pool.clone();
+    web::block(move || {
+        let mut connection = pool.get()?;
+        query(&mut connection)
+    }).await?
 }
\ No newline at end of file
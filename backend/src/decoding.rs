@@ -0,0 +1,288 @@
+use std::{
+    fs::File,
+    io::{BufReader, Cursor, Seek, SeekFrom},
+    path::Path
+};
+
+use hound::WavReader;
+use opus_decoder::OpusDecoder;
+use symphonia::core::{
+    audio::SampleBuffer,
+    sample::SampleFormat,
+    codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, Packet},
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+    probe::Hint
+};
+
+use crate::{error::AppError, samples::PcmFormat};
+
+/// Sample rate Opus streams are always decoded at.
+const OPUS_SAMPLE_RATE: u32 = 48_000;
+
+/// Number of frames read from a WAV file at a time.
+const WAV_BLOCK_FRAMES: usize = 4096;
+
+/// Where decoded samples come from.
+enum Source {
+    /// WAV files hound can read, which are read directly so every bit depth is kept as it is
+    Wav(WavReader<BufReader<Box<dyn MediaSource>>>),
+    /// Any other format and codec Symphonia supports
+    Track {
+        format: Box<dyn FormatReader>,
+        decoder: Box<dyn Decoder>,
+        track_id: u32
+    },
+    /// Ogg Opus, which Symphonia can read but not decode
+    Opus {
+        format: Box<dyn FormatReader>,
+        decoder: Box<OpusDecoder>,
+        track_id: u32,
+        pcm: Vec<f32>
+    }
+}
+
+/// An uploaded audio file, decoded a block of samples at a time so the whole file never has to be in memory.
+///
+/// The format is found from the file's contents rather than the content type the client sent.
+/// WAV (any bit depth), AIFF, MP3, FLAC, Ogg Vorbis, Ogg Opus and AAC or ALAC in MP4/M4A files are supported.
+pub struct UploadDecoder {
+    channels: u16,
+    sample_rate: u32,
+    format: PcmFormat,
+    /// Number of frames in the file, if the container says
+    total_frames: Option<u64>,
+    source: Source,
+    /// Samples decoded while the file was opened, returned by the first call to `next_block`
+    pending: Vec<f64>
+}
+
+impl UploadDecoder {
+    /// Opens a spooled upload.
+    pub fn open(path: &Path) -> Result<Self, AppError> {
+        match File::open(path) {
+            Ok(file) => UploadDecoder::new(Box::new(file)),
+            Err(_) => Err(AppError::Internal("Error opening upload".to_string()))
+        }
+    }
+
+    /// Opens an upload held in memory.
+    pub fn from_bytes(file_bytes: Vec<u8>) -> Result<Self, AppError> {
+        UploadDecoder::new(Box::new(Cursor::new(file_bytes)))
+    }
+
+    pub fn new(mut source: Box<dyn MediaSource>) -> Result<Self, AppError> {
+        let is_wav = WavReader::new(&mut source).is_ok();
+        if source.seek(SeekFrom::Start(0)).is_err() {
+            return Err(AppError::Internal("Error reading upload".to_string()));
+        }
+        let decoder = match is_wav {
+            true => UploadDecoder::open_wav(source)?,
+            false => UploadDecoder::open_other(source)?
+        };
+        if decoder.channels == 0 || decoder.channels > 2 {
+            return Err(AppError::Validation("Only mono and stereo audio is supported".to_string()));
+        }
+        Ok(decoder)
+    }
+
+    fn open_wav(source: Box<dyn MediaSource>) -> Result<Self, AppError> {
+        let reader = match WavReader::new(BufReader::new(source)) {
+            Ok(reader) => reader,
+            Err(_) => return Err(AppError::Validation("Invalid audio file".to_string()))
+        };
+        if reader.duration() == 0 {
+            return Err(AppError::Validation("The audio file is empty".to_string()));
+        }
+        let spec = reader.spec();
+        Ok(UploadDecoder {
+            channels: spec.channels,
+            sample_rate: spec.sample_rate,
+            format: PcmFormat::of(&spec),
+            total_frames: Some(reader.duration() as u64),
+            source: Source::Wav(reader),
+            pending: Vec::new()
+        })
+    }
+
+    fn open_other(source: Box<dyn MediaSource>) -> Result<Self, AppError> {
+        let source = MediaSourceStream::new(source, Default::default());
+        // Gapless playback trims encoder delay and padding, so songs don't start or end with silence
+        let format_options = FormatOptions { enable_gapless: true, ..Default::default() };
+        let probed = symphonia::default::get_probe().format(&Hint::new(), source, &format_options, &MetadataOptions::default());
+        let probed = match probed {
+            Ok(probed) => probed,
+            Err(_) => return Err(AppError::Validation("Unsupported or invalid audio file".to_string()))
+        };
+        let mut format = probed.format;
+
+        let track = format.tracks().iter().find(|track| track.codec_params.codec != CODEC_TYPE_NULL);
+        let (track_id, params) = match track {
+            Some(track) => (track.id, track.codec_params.clone()),
+            None => return Err(AppError::Validation("The audio file has no audio track".to_string()))
+        };
+        if params.codec == CODEC_TYPE_OPUS {
+            return UploadDecoder::open_opus(format, track_id, &params);
+        }
+
+        let decoder = symphonia::default::get_codecs().make(&params, &DecoderOptions::default());
+        let mut decoder = match decoder {
+            Ok(decoder) => decoder,
+            Err(_) => return Err(AppError::Validation("Unsupported audio codec".to_string()))
+        };
+
+        // The channels and sample rate aren't always known until the first packet is decoded
+        let mut pending = Vec::new();
+        let (channels, sample_rate) = match decode_packet(format.as_mut(), decoder.as_mut(), track_id, &mut pending)? {
+            Some(spec) => spec,
+            None => return Err(AppError::Validation("The audio file is empty".to_string()))
+        };
+        // Lossy codecs have no bit depth, and are treated like high resolution files
+        let pcm_format = match (params.sample_format, params.bits_per_sample) {
+            (Some(SampleFormat::F32 | SampleFormat::F64), _) | (_, None) => PcmFormat::Float,
+            (_, Some(bits)) if bits <= 16 => PcmFormat::Int(16),
+            (_, Some(bits)) if bits <= 24 => PcmFormat::Int(24),
+            _ => PcmFormat::Int(32)
+        };
+
+        Ok(UploadDecoder {
+            channels,
+            sample_rate,
+            format: pcm_format,
+            total_frames: params.n_frames,
+            source: Source::Track { format, decoder, track_id },
+            pending
+        })
+    }
+
+    fn open_opus(format: Box<dyn FormatReader>, track_id: u32, params: &CodecParameters) -> Result<Self, AppError> {
+        let channels = params.channels.map(|channels| channels.count()).unwrap_or(0);
+        if channels == 0 || channels > 2 {
+            return Err(AppError::Validation("Only mono and stereo audio is supported".to_string()));
+        }
+        let decoder = match OpusDecoder::new(OPUS_SAMPLE_RATE, channels) {
+            Ok(decoder) => decoder,
+            Err(_) => return Err(AppError::Internal("Error setting up Opus decoder".to_string()))
+        };
+        let pcm = vec![0f32; decoder.max_frame_size_per_channel() * channels];
+
+        Ok(UploadDecoder {
+            channels: channels as u16,
+            sample_rate: OPUS_SAMPLE_RATE,
+            format: PcmFormat::Float,
+            total_frames: params.n_frames,
+            source: Source::Opus { format, decoder: Box::new(decoder), track_id, pcm },
+            pending: Vec::new()
+        })
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// The format of the file's samples, which tells whether converting them to another format loses precision.
+    pub fn format(&self) -> PcmFormat {
+        self.format
+    }
+
+    /// The number of frames in the file, which isn't known for every format until it has been decoded.
+    pub fn total_frames(&self) -> Option<u64> {
+        self.total_frames
+    }
+
+    /// Decodes the next block of interleaved samples into `block`, scaled to full scale (`-1.0..1.0`).
+    /// Returns `false` once the whole file has been read.
+    pub fn next_block(&mut self, block: &mut Vec<f64>) -> Result<bool, AppError> {
+        block.clear();
+        if !self.pending.is_empty() {
+            std::mem::swap(block, &mut self.pending);
+            return Ok(true);
+        }
+
+        match &mut self.source {
+            Source::Wav(reader) => {
+                let limit = WAV_BLOCK_FRAMES * self.channels as usize;
+                let read = match self.format {
+                    PcmFormat::Int(bits) => {
+                        // Integer samples are read as i32 whatever their depth
+                        let scale = (1i64 << (bits - 1)) as f64;
+                        reader.samples::<i32>().take(limit).try_for_each(|sample| {
+                            block.push(sample? as f64 / scale);
+                            Ok::<(), hound::Error>(())
+                        })
+                    },
+                    PcmFormat::Float => reader.samples::<f32>().take(limit).try_for_each(|sample| {
+                        block.push(f64::from(sample?));
+                        Ok(())
+                    })
+                };
+                if read.is_err() {
+                    return Err(AppError::Validation("Error reading samples".to_string()));
+                }
+                Ok(!block.is_empty())
+            },
+            Source::Track { format, decoder, track_id } => {
+                let decoded = decode_packet(format.as_mut(), decoder.as_mut(), *track_id, block)?;
+                Ok(decoded.is_some())
+            },
+            Source::Opus { format, decoder, track_id, pcm } => {
+                let channels = self.channels as usize;
+                while let Some(packet) = next_packet(format.as_mut(), *track_id)? {
+                    let frames = match decoder.decode_float(&packet.data, pcm, false) {
+                        Ok(frames) => frames,
+                        Err(_) => return Err(AppError::Validation("Error decoding audio file".to_string()))
+                    };
+                    // Drop the pre-skip at the start of the stream and the padding at the end
+                    let start = (packet.trim_start() as usize).min(frames);
+                    let end = frames.saturating_sub(packet.trim_end() as usize).max(start);
+                    if end > start {
+                        block.extend(pcm[start * channels..end * channels].iter().map(|sample| f64::from(*sample)));
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Gets the next packet of a track, or `None` at the end of the file.
+fn next_packet(format: &mut dyn FormatReader, track_id: u32) -> Result<Option<Packet>, AppError> {
+    loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => return Ok(Some(packet)),
+            Ok(_) => continue,
+            Err(SymphoniaError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(_) => return Err(AppError::Validation("Error reading audio file".to_string()))
+        }
+    }
+}
+
+/// Decodes packets of a track until one has audio, adding its samples to `block`.
+/// Returns the channels and sample rate of the audio, or `None` at the end of the file.
+fn decode_packet(format: &mut dyn FormatReader, decoder: &mut dyn Decoder, track_id: u32, block: &mut Vec<f64>) -> Result<Option<(u16, u32)>, AppError> {
+    while let Some(packet) = next_packet(format, track_id)? {
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt frame is skipped rather than failing the whole upload
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(_) => return Err(AppError::Validation("Error decoding audio file".to_string()))
+        };
+        if decoded.frames() == 0 {
+            continue;
+        }
+
+        let spec = *decoded.spec();
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        block.extend(buffer.samples().iter().map(|sample| f64::from(*sample)));
+        return Ok(Some((spec.channels.count() as u16, spec.rate)));
+    }
+    Ok(None)
+}
\ No newline at end of file
@@ -0,0 +1,650 @@
+use std::{
+    collections::VecDeque,
+    env,
+    io::{Cursor, Write},
+    num::NonZeroU32
+};
+
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    serialize::{self, IsNull, Output, ToSql}
+};
+use dotenvy::dotenv;
+use flacenc::{
+    bitsink::ByteSink,
+    component::BitRepr,
+    error::Verify,
+    source::MemSource
+};
+use hound::{WavReader, WavSpec};
+use mp3lame_encoder::{
+    ffi,
+    max_required_buffer_size,
+    Bitrate,
+    Builder,
+    Encoder,
+    FlushGap,
+    InterleavedPcm,
+    MonoPcm,
+    Quality
+};
+use serde::Serialize;
+
+use crate::{
+    error::AppError,
+    samples::write_wav,
+    schema::sql_types::SegmentCodec
+};
+
+/// Samples the audio in an MP3 stream is delayed by, at the stream's sample rate: 576 added by LAME and 529 by the decoder.
+const MP3_DELAY: u64 = 576 + 529;
+
+/// Frames of the segment before that are encoded ahead of a segment encoded on its own, so the encoder has settled by the time it starts.
+const MP3_PRE_ROLL_FRAMES: u64 = 4;
+
+/// Frames of the segment after that are encoded with a segment encoded on its own, so its last frames aren't cut short.
+const MP3_POST_ROLL_FRAMES: u64 = 2;
+
+/// The sample rates MP3 supports, from lowest to highest.
+const MP3_SAMPLE_RATES: [u32; 9] = [8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+
+/// The codec a rendition's samples are stored in, kept in the `segment_codec` postgres enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = SegmentCodec)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// Uncompressed PCM, compressed with zlib when sent
+    Wav,
+    /// MP3 at a constant bitrate
+    Mp3,
+    /// Losslessly compressed PCM
+    Flac
+}
+
+impl Codec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Wav => "wav",
+            Codec::Mp3 => "mp3",
+            Codec::Flac => "flac"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Codec> {
+        match name.to_lowercase().as_str() {
+            "wav" => Some(Codec::Wav),
+            "mp3" => Some(Codec::Mp3),
+            "flac" => Some(Codec::Flac),
+            _ => None
+        }
+    }
+
+    /// Gets the codec a media type such as `audio/flac` refers to.
+    pub fn from_content_type(content_type: &str) -> Option<Codec> {
+        match content_type.to_lowercase().as_str() {
+            "audio/wav" | "audio/wave" | "audio/x-wav" => Some(Codec::Wav),
+            "audio/mpeg" | "audio/mp3" => Some(Codec::Mp3),
+            "audio/flac" | "audio/x-flac" => Some(Codec::Flac),
+            _ => None
+        }
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        match self {
+            Codec::Wav | Codec::Flac => true,
+            Codec::Mp3 => false
+        }
+    }
+
+    /// The file extension of samples stored in this codec.
+    pub fn extension(&self) -> &'static str {
+        self.as_str()
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Codec::Wav => "audio/wav",
+            Codec::Mp3 => "audio/mpeg",
+            Codec::Flac => "audio/flac"
+        }
+    }
+}
+
+impl ToSql<SegmentCodec, Pg> for Codec {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<SegmentCodec, Pg> for Codec {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"wav" => Ok(Codec::Wav),
+            b"mp3" => Ok(Codec::Mp3),
+            b"flac" => Ok(Codec::Flac),
+            _ => Err("Unrecognized segment codec".into())
+        }
+    }
+}
+
+/// One of the renditions new songs' samples are encoded in when they are uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentFormat {
+    pub codec: Codec,
+    pub bitrate_kbps: u32
+}
+
+/// Reads the renditions every uploaded song is encoded in from `SEGMENT_RENDITIONS`,
+/// a comma separated list of `codec:bitrate` entries, or just `codec` for lossless codecs.
+///
+/// Defaults to `mp3:64,mp3:128,mp3:256,flac`.
+pub fn renditions_from_env() -> Vec<SegmentFormat> {
+    dotenv().ok();
+
+    let renditions = env::var("SEGMENT_RENDITIONS").unwrap_or("mp3:64,mp3:128,mp3:256,flac".to_string());
+    let mut formats: Vec<SegmentFormat> = Vec::new();
+    for rendition in renditions.split(',').map(str::trim).filter(|rendition| !rendition.is_empty()) {
+        let (codec, bitrate) = match rendition.split_once(':') {
+            Some((codec, bitrate)) => (codec, Some(bitrate)),
+            None => (rendition, None)
+        };
+        let codec = match Codec::from_name(codec) {
+            Some(codec) => codec,
+            None => panic!("Unknown codec in SEGMENT_RENDITIONS: {}", codec)
+        };
+        let bitrate_kbps = match (codec, bitrate) {
+            (Codec::Wav | Codec::Flac, _) => 0,
+            (Codec::Mp3, Some(bitrate)) => match bitrate.parse() {
+                Ok(bitrate) if mp3_bitrate(bitrate).is_some() => bitrate,
+                _ => panic!("Unsupported MP3 bitrate in SEGMENT_RENDITIONS: {}", bitrate)
+            },
+            (Codec::Mp3, None) => panic!("MP3 renditions in SEGMENT_RENDITIONS need a bitrate, e.g. mp3:128")
+        };
+
+        let format = SegmentFormat { codec, bitrate_kbps };
+        if formats.iter().any(|existing| existing.name() == format.name()) {
+            panic!("Duplicate rendition in SEGMENT_RENDITIONS: {}", rendition);
+        }
+        formats.push(format);
+    }
+    if formats.is_empty() {
+        panic!("SEGMENT_RENDITIONS must list at least one rendition");
+    }
+    formats
+}
+
+impl SegmentFormat {
+    /// The name of the rendition, such as `mp3_128` or `lossless`, used in urls and storage keys.
+    pub fn name(&self) -> String {
+        match self.codec {
+            Codec::Wav | Codec::Flac => "lossless".to_string(),
+            Codec::Mp3 => format!("{}_{}", self.codec.as_str(), self.bitrate_kbps)
+        }
+    }
+
+    /// The bitrate recorded for renditions in this format, which is only meaningful for lossy codecs.
+    pub fn recorded_bitrate(&self) -> Option<i32> {
+        match self.codec {
+            Codec::Wav | Codec::Flac => None,
+            Codec::Mp3 => Some(self.bitrate_kbps as i32)
+        }
+    }
+
+    /// Starts encoding the samples of a song with the given channels and sample rate into this format.
+    pub fn song_encoder(&self, channels: u16, sample_rate: u32) -> Result<SongEncoder, AppError> {
+        match self.codec {
+            Codec::Wav => Ok(SongEncoder::Wav),
+            Codec::Mp3 => Ok(SongEncoder::Mp3(Mp3Segmenter::new(channels, sample_rate, self.bitrate_kbps)?)),
+            Codec::Flac => Ok(SongEncoder::Flac)
+        }
+    }
+}
+
+/// Encodes the samples of a song into one format, one after another.
+pub enum SongEncoder {
+    Wav,
+    Flac,
+    /// MP3 samples are cut from one stream for the whole song, see [`Mp3Segmenter`]
+    Mp3(Mp3Segmenter)
+}
+
+impl SongEncoder {
+    /// Encodes the next WAV sample of the song, returning the samples that are done.
+    ///
+    /// WAV and FLAC samples are done straight away, MP3 samples once the frames after them have been encoded.
+    pub fn push(&mut self, wav_bytes: Vec<u8>) -> Result<Vec<Vec<u8>>, AppError> {
+        match self {
+            SongEncoder::Wav => Ok(vec![wav_bytes]),
+            SongEncoder::Flac => Ok(vec![wav_to_flac(wav_bytes)?]),
+            SongEncoder::Mp3(segmenter) => {
+                let (_, samples) = read_wav(wav_bytes)?;
+                segmenter.push(&samples)
+            }
+        }
+    }
+
+    /// Finishes encoding the song, returning the samples that weren't done yet.
+    pub fn finish(self) -> Result<Vec<Vec<u8>>, AppError> {
+        match self {
+            SongEncoder::Wav | SongEncoder::Flac => Ok(Vec::new()),
+            SongEncoder::Mp3(segmenter) => segmenter.finish()
+        }
+    }
+}
+
+fn mp3_bitrate(kbps: u32) -> Option<Bitrate> {
+    let bitrate = match kbps {
+        32 => Bitrate::Kbps32,
+        40 => Bitrate::Kbps40,
+        48 => Bitrate::Kbps48,
+        64 => Bitrate::Kbps64,
+        80 => Bitrate::Kbps80,
+        96 => Bitrate::Kbps96,
+        112 => Bitrate::Kbps112,
+        128 => Bitrate::Kbps128,
+        160 => Bitrate::Kbps160,
+        192 => Bitrate::Kbps192,
+        224 => Bitrate::Kbps224,
+        256 => Bitrate::Kbps256,
+        320 => Bitrate::Kbps320,
+        _ => return None
+    };
+    Some(bitrate)
+}
+
+/// The sample rate a song is encoded to MP3 at: its own if MP3 supports it, otherwise one it can be resampled to easily.
+fn mp3_sample_rate(sample_rate: u32) -> u32 {
+    if MP3_SAMPLE_RATES.contains(&sample_rate) {
+        return sample_rate;
+    }
+    let divides = MP3_SAMPLE_RATES.iter().rev().find(|rate| sample_rate.is_multiple_of(**rate));
+    let below = MP3_SAMPLE_RATES.iter().rev().find(|rate| **rate < sample_rate);
+    *divides.or(below).unwrap_or(&MP3_SAMPLE_RATES[0])
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b)
+    }
+}
+
+/// Where the frames of an MP3 stream encoded from the start of a song fall in the song.
+///
+/// Each frame belongs to the segment of the song its last sample is in, once the delay of the encoder and decoder
+/// is taken off, so cutting the stream between segments never leaves out or repeats any audio.
+#[derive(Clone, Copy, Debug)]
+struct Mp3Grid {
+    sample_rate: u64,
+    mp3_sample_rate: u64,
+    samples_per_frame: u64
+}
+
+impl Mp3Grid {
+    fn new(sample_rate: u32) -> Self {
+        let mp3_sample_rate = mp3_sample_rate(sample_rate) as u64;
+        // MPEG 2 and 2.5, used for the lower sample rates, have half as many samples in a frame
+        let samples_per_frame = match mp3_sample_rate >= 32000 {
+            true => 1152,
+            false => 576
+        };
+        Mp3Grid { sample_rate: sample_rate as u64, mp3_sample_rate, samples_per_frame }
+    }
+
+    /// The sample of the song that frame `frame` ends with. Frames before the song starts end with its first sample.
+    fn frame_end(&self, frame: u64) -> u64 {
+        ((frame + 1) * self.samples_per_frame).saturating_sub(MP3_DELAY + 1) * self.sample_rate / self.mp3_sample_rate
+    }
+
+    /// The first frame of the segment that starts at sample `start` of the song.
+    fn first_frame(&self, start: u64) -> u64 {
+        if start == 0 {
+            return 0;
+        }
+        let start = (start * self.mp3_sample_rate).div_ceil(self.sample_rate);
+        (start + MP3_DELAY + 1).div_ceil(self.samples_per_frame) - 1
+    }
+
+    /// The frame an encoder started at sample `start` of the song begins with.
+    fn frame_at(&self, start: u64) -> u64 {
+        start * self.mp3_sample_rate / self.sample_rate / self.samples_per_frame
+    }
+
+    /// The smallest number of samples of the song that make up a whole number of frames. An encoder started
+    /// part way through the song has to start at a multiple of this to make the same frames as one started at the beginning.
+    fn alignment(&self) -> u64 {
+        let frame = self.samples_per_frame * self.sample_rate;
+        frame / gcd(frame, self.mp3_sample_rate)
+    }
+
+    /// The number of samples of the song in `frames` frames, rounded down.
+    fn frames_len(&self, frames: u64) -> u64 {
+        frames * self.samples_per_frame * self.sample_rate / self.mp3_sample_rate
+    }
+}
+
+/// When a segment of a song starts in its MP3 stream, in seconds.
+///
+/// Segments are cut from the stream between frames, so this is at most a frame after the segment starts in the song,
+/// plus the delay at the start of the stream.
+pub fn mp3_segment_start(start_frame: u64, sample_rate: u32) -> f64 {
+    let grid = Mp3Grid::new(sample_rate);
+    (grid.first_frame(start_frame) * grid.samples_per_frame) as f64 / grid.mp3_sample_rate as f64
+}
+
+/// The parts of an MP3 frame header needed to find where frames and the data in them are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mp3FrameHeader {
+    /// The length of the whole frame in bytes
+    pub len: usize,
+    /// Whether the frame is MPEG 1, rather than MPEG 2 or 2.5
+    pub mpeg1: bool,
+    pub mono: bool
+}
+
+/// Reads the header of the layer III frame at the start of `mp3`.
+pub fn mp3_frame_header(mp3: &[u8]) -> Option<Mp3FrameHeader> {
+    if mp3.len() < 4 || mp3[0] != 0xFF || mp3[1] & 0xE0 != 0xE0 || (mp3[1] >> 1) & 0b11 != 0b01 {
+        return None;
+    }
+    // MPEG 1, or MPEG 2 and 2.5 which have half and a quarter of the sample rate
+    let version = (mp3[1] >> 3) & 0b11;
+    let mpeg1 = version == 0b11;
+    let bitrates: [usize; 15] = match mpeg1 {
+        true => [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+        false => [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160]
+    };
+    let bitrate = bitrates.get((mp3[2] >> 4) as usize).copied().unwrap_or(0);
+    let sample_rate = match ((mp3[2] >> 2) & 0b11, version) {
+        (3, _) | (_, 0b01) => return None,
+        (index, version) => [44100, 48000, 32000][index as usize] >> (3 - version.max(1))
+    };
+    if bitrate == 0 {
+        return None;
+    }
+    let padding = ((mp3[2] >> 1) & 1) as usize;
+    let len = match mpeg1 {
+        true => 144 * bitrate * 1000 / sample_rate + padding,
+        false => 72 * bitrate * 1000 / sample_rate + padding
+    };
+
+    Some(Mp3FrameHeader { len, mpeg1, mono: mp3[3] >> 6 == 0b11 })
+}
+
+/// Sets up LAME to encode at a constant bitrate.
+///
+/// The bit reservoir is turned off, so frames don't borrow space from the ones before them and a segment
+/// can be decoded from its first frame.
+fn mp3_encoder(channels: u16, sample_rate: u32, bitrate_kbps: u32) -> Result<Encoder, AppError> {
+    let bitrate = match mp3_bitrate(bitrate_kbps) {
+        Some(bitrate) => bitrate,
+        None => return Err(AppError::Internal("Unsupported MP3 bitrate".to_string()))
+    };
+    if channels == 0 || channels > 2 {
+        return Err(AppError::Validation("Only mono and stereo audio can be encoded to MP3".to_string()));
+    }
+
+    let encoder_error = |_| AppError::Internal("Error setting up MP3 encoder".to_string());
+    let mut builder = match Builder::new() {
+        Some(builder) => builder,
+        None => return Err(AppError::Internal("Error setting up MP3 encoder".to_string()))
+    };
+    builder.set_num_channels(channels as u8).map_err(encoder_error)?;
+    builder.set_sample_rate(sample_rate).map_err(encoder_error)?;
+    builder.set_output_sample_rate(NonZeroU32::new(mp3_sample_rate(sample_rate))).map_err(encoder_error)?;
+    builder.set_brate(bitrate).map_err(encoder_error)?;
+    builder.set_quality(Quality::Good).map_err(encoder_error)?;
+    builder.set_to_write_vbr_tag(false).map_err(encoder_error)?;
+    // The builder keeps ownership of the pointer, which is only used while it is alive
+    let disabled = unsafe { ffi::lame_set_disable_reservoir(builder.as_ptr(), 1) };
+    if disabled != 0 {
+        return Err(AppError::Internal("Error setting up MP3 encoder".to_string()));
+    }
+    builder.build().map_err(encoder_error)
+}
+
+/// Encodes the samples of a song to MP3 one after another with a single encoder, cutting the stream between
+/// frames into one MP3 sample for each, see [`Mp3Grid`].
+///
+/// A new encoder would add silence to the start and end of every sample, which would be heard as gaps
+/// when they are played back to back.
+pub struct Mp3Segmenter {
+    encoder: Encoder,
+    grid: Mp3Grid,
+    channels: usize,
+    /// The frame of the song's stream the encoder started at
+    first_frame: u64,
+    /// The number of frames encoded so far
+    frames: u64,
+    /// The number of samples of the song encoded so far, from the start of the song
+    end: u64,
+    /// Encoded bytes that don't make up a whole frame yet
+    partial: Vec<u8>,
+    /// Where each sample whose frames haven't all been encoded yet ends in the song, with its frames so far
+    samples: VecDeque<(u64, Vec<u8>)>
+}
+
+impl Mp3Segmenter {
+    /// Starts encoding a song from the beginning.
+    pub fn new(channels: u16, sample_rate: u32, bitrate_kbps: u32) -> Result<Self, AppError> {
+        Mp3Segmenter::starting_at(channels, sample_rate, bitrate_kbps, 0)
+    }
+
+    /// Starts encoding a song from sample `start`, which has to be a multiple of [`Mp3Grid::alignment`]
+    /// to make the same frames as an encoder started at the beginning.
+    fn starting_at(channels: u16, sample_rate: u32, bitrate_kbps: u32, start: u64) -> Result<Self, AppError> {
+        let grid = Mp3Grid::new(sample_rate);
+        Ok(Mp3Segmenter {
+            encoder: mp3_encoder(channels, sample_rate, bitrate_kbps)?,
+            grid,
+            channels: channels as usize,
+            first_frame: grid.frame_at(start),
+            frames: 0,
+            end: start,
+            partial: Vec::new(),
+            samples: VecDeque::new()
+        })
+    }
+
+    /// Encodes the next sample of the song from its interleaved 16-bit samples, returning the MP3 samples that are done.
+    pub fn push(&mut self, pcm: &[i16]) -> Result<Vec<Vec<u8>>, AppError> {
+        let frames = pcm.len() / self.channels;
+        self.end += frames as u64;
+        self.samples.push_back((self.end, Vec::new()));
+
+        let mut mp3_bytes = Vec::with_capacity(max_required_buffer_size(frames));
+        let encoded = match self.channels {
+            1 => self.encoder.encode_to_vec(MonoPcm(pcm), &mut mp3_bytes),
+            _ => self.encoder.encode_to_vec(InterleavedPcm(pcm), &mut mp3_bytes)
+        };
+        if encoded.is_err() {
+            return Err(AppError::Internal("Error encoding MP3".to_string()));
+        }
+        self.partial.extend_from_slice(&mp3_bytes);
+        Ok(self.take_frames(false))
+    }
+
+    /// Finishes the stream, returning the MP3 samples that weren't done yet.
+    /// The encoder's padding goes in the last sample.
+    pub fn finish(mut self) -> Result<Vec<Vec<u8>>, AppError> {
+        // The flush writes at most 7200 bytes
+        let mut mp3_bytes = Vec::with_capacity(7200);
+        if self.encoder.flush_to_vec::<FlushGap>(&mut mp3_bytes).is_err() {
+            return Err(AppError::Internal("Error encoding MP3".to_string()));
+        }
+        self.partial.extend_from_slice(&mp3_bytes);
+
+        let mut done = self.take_frames(true);
+        if !self.partial.is_empty() {
+            return Err(AppError::Internal("Error encoding MP3".to_string()));
+        }
+        done.extend(self.samples.into_iter().map(|(_, frames)| frames));
+        Ok(done)
+    }
+
+    /// Moves the whole frames encoded so far into the samples they belong to, returning the samples that are done.
+    ///
+    /// Frames after the end of the song so far wait for the next sample, unless the stream is `finished`
+    /// and they are the encoder's padding.
+    fn take_frames(&mut self, finished: bool) -> Vec<Vec<u8>> {
+        let mut done = Vec::new();
+        let mut position = 0;
+        while let Some(header) = mp3_frame_header(&self.partial[position..]) {
+            if position + header.len > self.partial.len() {
+                break;
+            }
+            let frame_end = self.grid.frame_end(self.first_frame + self.frames);
+            // A sample is done once a frame of a later sample has been encoded
+            while self.samples.len() > 1 && self.samples[0].0 <= frame_end {
+                if let Some((_, frames)) = self.samples.pop_front() {
+                    done.push(frames);
+                }
+            }
+            let sample = match self.samples.front_mut() {
+                Some(sample) if sample.0 > frame_end || finished => sample,
+                _ => break
+            };
+            sample.1.extend_from_slice(&self.partial[position..position + header.len]);
+            position += header.len;
+            self.frames += 1;
+        }
+        self.partial.drain(..position);
+        done
+    }
+}
+
+/// Encodes one segment of a song, held as WAV samples, to MP3 on its own.
+///
+/// The end of the segment before it and the start of the one after are encoded with it, and it is cut from the stream
+/// where [`Mp3Segmenter`] would cut it from one for the whole song. This way segments encoded on their own
+/// still play back to back without gaps. `start` is the sample of the song the segment starts at.
+pub fn wav_segment_to_mp3(previous: Option<Vec<u8>>, sample: Vec<u8>, next: Option<Vec<u8>>, start: u64, bitrate_kbps: u32) -> Result<Vec<u8>, AppError> {
+    let (spec, pcm) = read_wav(sample)?;
+    let previous = match previous {
+        Some(previous) => read_wav(previous)?.1,
+        None => Vec::new()
+    };
+    let next = match next {
+        Some(next) => read_wav(next)?.1,
+        None => Vec::new()
+    };
+    let channels = spec.channels.max(1) as usize;
+    let grid = Mp3Grid::new(spec.sample_rate);
+
+    // Start a few frames before the segment, on a frame of the whole song's stream, without going back past the segment before
+    let alignment = grid.alignment();
+    let earliest = start.saturating_sub((previous.len() / channels) as u64).div_ceil(alignment) * alignment;
+    let from = match start.saturating_sub(grid.frames_len(MP3_PRE_ROLL_FRAMES)) / alignment * alignment {
+        from if from < earliest => earliest.min(start),
+        from => from
+    };
+    let pre_roll = (start - from) as usize * channels;
+    let post_roll = (grid.frames_len(MP3_POST_ROLL_FRAMES) as usize * channels).min(next.len());
+
+    let mut segmenter = Mp3Segmenter::starting_at(spec.channels, spec.sample_rate, bitrate_kbps, from)?;
+    let mut done = Vec::new();
+    if pre_roll > 0 {
+        done.extend(segmenter.push(&previous[previous.len() - pre_roll..])?);
+    }
+    done.extend(segmenter.push(&pcm)?);
+    if post_roll > 0 {
+        done.extend(segmenter.push(&next[..post_roll])?);
+    }
+    done.extend(segmenter.finish()?);
+
+    let segment = match pre_roll {
+        0 => done.into_iter().next(),
+        _ => done.into_iter().nth(1)
+    };
+    match segment {
+        Some(segment) => Ok(segment),
+        None => Err(AppError::Internal("Error encoding MP3".to_string()))
+    }
+}
+
+/// Reads the interleaved samples of a WAV file, such as a stored sample, as 16-bit samples for the MP3 encoder.
+fn read_wav(wav_bytes: Vec<u8>) -> Result<(WavSpec, Vec<i16>), AppError> {
+    let (spec, bits, samples) = read_pcm(wav_bytes, 16)?;
+    Ok((spec, samples.into_iter().map(|sample| (sample << (16 - bits)) as i16).collect()))
+}
+
+/// Reads the interleaved integer samples of a WAV file in any format, with their bit depth.
+///
+/// Samples deeper than `max_bits` and floating point samples are rounded to `max_bits`.
+fn read_pcm(wav_bytes: Vec<u8>, max_bits: u16) -> Result<(WavSpec, u16, Vec<i32>), AppError> {
+    let mut reader = match WavReader::new(Cursor::new(wav_bytes)) {
+        Ok(reader) => reader,
+        Err(_) => return Err(AppError::Internal("Error opening sample".to_string()))
+    };
+    let spec = reader.spec();
+    let bits = match spec.sample_format {
+        hound::SampleFormat::Int => spec.bits_per_sample.min(max_bits),
+        hound::SampleFormat::Float => max_bits
+    };
+    let max = ((1i64 << (bits - 1)) - 1) as f64;
+    let samples: Result<Vec<i32>, _> = match spec.sample_format {
+        hound::SampleFormat::Int if spec.bits_per_sample == bits => reader.samples::<i32>().collect(),
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - bits)) as f64;
+            reader.samples::<i32>().map(|sample| sample.map(|sample| (sample as f64 / scale).round().clamp(-max - 1.0, max) as i32)).collect()
+        },
+        hound::SampleFormat::Float => reader.samples::<f32>().map(|sample| sample.map(|sample| (sample as f64 * (max + 1.0)).round().clamp(-max - 1.0, max) as i32)).collect()
+    };
+    match samples {
+        Ok(samples) => Ok((spec, bits, samples)),
+        Err(_) => Err(AppError::Internal("Error reading sample".to_string()))
+    }
+}
+
+/// Encodes a WAV file, such as a stored sample, to FLAC.
+///
+/// FLAC holds at most 24 bits, so deeper and floating point samples are rounded to 24 bits.
+pub fn wav_to_flac(wav_bytes: Vec<u8>) -> Result<Vec<u8>, AppError> {
+    let (spec, bits, samples) = read_pcm(wav_bytes, 24)?;
+
+    let config = match flacenc::config::Encoder::default().into_verified() {
+        Ok(config) => config,
+        Err(_) => return Err(AppError::Internal("Error setting up FLAC encoder".to_string()))
+    };
+    let source = MemSource::from_samples(&samples, spec.channels as usize, bits as usize, spec.sample_rate as usize);
+    let mut stream = match flacenc::encode_with_fixed_block_size(&config, source, config.block_size) {
+        Ok(stream) => stream,
+        Err(_) => return Err(AppError::Internal("Error encoding FLAC".to_string()))
+    };
+    // The encoder records the shorter last block as the minimum block size, which makes
+    // strict decoders treat the stream as variable block size and reject its frames
+    let block_size = stream.stream_info().max_block_size();
+    if stream.stream_info_mut().set_block_sizes(block_size, block_size).is_err() {
+        return Err(AppError::Internal("Error encoding FLAC".to_string()));
+    }
+
+    let mut sink = ByteSink::new();
+    if stream.write(&mut sink).is_err() {
+        return Err(AppError::Internal("Error encoding FLAC".to_string()));
+    }
+    Ok(sink.into_inner())
+}
+
+/// Decodes a FLAC sample back to a 16-bit WAV file, to be encoded to MP3 or measured.
+pub fn flac_to_wav(flac_bytes: Vec<u8>) -> Result<Vec<u8>, AppError> {
+    let source = match rodio::Decoder::new_flac(Cursor::new(flac_bytes)) {
+        Ok(source) => source,
+        Err(_) => return Err(AppError::Internal("Error decoding FLAC sample".to_string()))
+    };
+    let spec = WavSpec {
+        channels: rodio::Source::channels(&source),
+        sample_rate: rodio::Source::sample_rate(&source),
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int
+    };
+    let samples: Vec<i16> = source.collect();
+
+    let mut buffer = Cursor::new(Vec::new());
+    if write_wav(&mut buffer, spec, &samples).is_err() {
+        return Err(AppError::Internal("Error decoding FLAC sample".to_string()));
+    }
+    Ok(buffer.into_inner())
+}
\ No newline at end of file
@@ -0,0 +1,141 @@
+use std::fmt;
+
+use actix_web::{
+    error::BlockingError,
+    http::{
+        header::RETRY_AFTER,
+        StatusCode
+    },
+    HttpResponse,
+    ResponseError
+};
+use serde::Serialize;
+
+/// The error type used throughout the backend.
+///
+/// Every variant maps to an HTTP status code, and is sent to clients as
+/// `{"error": "<message>", "code": "<code>"}`.
+#[derive(Debug)]
+pub enum AppError {
+    /// The request was malformed or failed validation (400)
+    Validation(String),
+    /// The caller isn't logged in or gave the wrong credentials (401)
+    Unauthorized(String),
+    /// The caller isn't allowed to do this (403)
+    Forbidden(String),
+    /// The requested resource doesn't exist (404)
+    NotFound(String),
+    /// The request conflicts with existing data (409)
+    Conflict(String),
+    /// The upload is larger than the server accepts (413)
+    PayloadTooLarge(String),
+    /// The caller has made too many attempts and has to wait the given number of seconds (429)
+    TooManyRequests(String, u64),
+    /// A database query failed (500)
+    Database(String),
+    /// The storage backend failed (500)
+    Storage(String),
+    /// Something else went wrong on the server (500)
+    Internal(String),
+    /// The database or the blocking thread pool can't take the request right now (503)
+    Unavailable(String)
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+    code: &'static str
+}
+
+impl AppError {
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::Validation(message)
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::PayloadTooLarge(message)
+            | AppError::TooManyRequests(message, _)
+            | AppError::Database(message)
+            | AppError::Storage(message)
+            | AppError::Internal(message)
+            | AppError::Unavailable(message) => message
+        }
+    }
+
+    /// A short machine readable name for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_error",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::TooManyRequests(_, _) => "too_many_requests",
+            AppError::Database(_) => "database_error",
+            AppError::Storage(_) => "storage_error",
+            AppError::Internal(_) => "internal_error",
+            AppError::Unavailable(_) => "service_unavailable"
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message())
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::TooManyRequests(_, _) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Database(_) | AppError::Storage(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let AppError::TooManyRequests(_, seconds) = self {
+            response.insert_header((RETRY_AFTER, seconds.to_string()));
+        }
+        response.json(ErrorBody {
+            error: self.message(),
+            code: self.code()
+        })
+    }
+}
+
+impl From<diesel::result::Error> for AppError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::NotFound => AppError::NotFound("Not found".to_string()),
+            err => {
+                println!("Database error: {:?}", err);
+                AppError::Database("Database error".to_string())
+            }
+        }
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for AppError {
+    fn from(err: diesel::r2d2::PoolError) -> Self {
+        println!("Error getting a database connection: {:?}", err);
+        AppError::Unavailable("Database is unavailable".to_string())
+    }
+}
+
+impl From<BlockingError> for AppError {
+    fn from(_: BlockingError) -> Self {
+        AppError::Unavailable("The server is too busy to handle the request".to_string())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,190 @@
+use std::{
+    convert::Infallible,
+    env
+};
+
+use actix_web::{
+    cookie::{
+        time::Duration,
+        Cookie,
+        SameSite
+    },
+    dev::Payload,
+    http::header::{
+        AUTHORIZATION,
+        USER_AGENT
+    },
+    web,
+    FromRequest,
+    HttpRequest
+};
+use futures::future::{
+    ready,
+    LocalBoxFuture,
+    Ready
+};
+
+use crate::{
+    auth::{
+        get_user,
+        touch_session
+    },
+    db::{self, DbPool},
+    error::AppError,
+    models::{Session, Users},
+    roles::Capability
+};
+
+/// Name of the HttpOnly cookie holding the session id.
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// The session id sent with the request, either in the `session_id` cookie
+/// or in an `Authorization: Bearer <session_id>` header.
+///
+/// Extraction fails with a 401 if neither is present. The session itself is not checked.
+pub struct SessionToken(pub uuid::Uuid);
+
+impl FromRequest for SessionToken {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let session_id = session_id_from_request(req);
+        ready(match session_id {
+            Some(session_id) => Ok(SessionToken(session_id)),
+            None => Err(AppError::Unauthorized("You must be logged in".to_string()))
+        })
+    }
+}
+
+/// The user making the request, resolved from their [`SessionToken`].
+///
+/// Extraction fails with a 401 if there is no session or it is invalid.
+pub struct AuthenticatedUser {
+    pub user: Users,
+    pub session_id: uuid::Uuid
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let session_id = session_id_from_request(req);
+        let pool = req.app_data::<web::Data<DbPool>>().cloned();
+
+        Box::pin(async move {
+            let session_id = match session_id {
+                Some(session_id) => session_id,
+                None => return Err(AppError::Unauthorized("You must be logged in".to_string()))
+            };
+            let pool = match pool {
+                Some(pool) => pool,
+                None => return Err(AppError::Internal("Database pool is not configured".to_string()))
+            };
+
+            let user = db::run(&pool, move |conn| {
+                let user = get_user(conn, &session_id)?;
+                // Failing to record activity shouldn't fail the request
+                if let Err(err) = touch_session(conn, &session_id) {
+                    println!("Error updating session activity: {:?}", err);
+                }
+                Ok(user)
+            }).await;
+            match user {
+                Ok(user) => Ok(AuthenticatedUser { user, session_id }),
+                Err(AppError::NotFound(_)) => Err(AppError::Unauthorized("Invalid or expired session".to_string())),
+                Err(err) => Err(err)
+            }
+        })
+    }
+}
+
+impl AuthenticatedUser {
+    /// Checks that the user's role grants the capability, failing with a 403 if it doesn't.
+    pub fn require(&self, capability: Capability) -> Result<(), AppError> {
+        if self.user.role.can(capability) {
+            Ok(())
+        }
+        else {
+            Err(AppError::Forbidden("You do not have permission to do this".to_string()))
+        }
+    }
+}
+
+/// Details about the client making the request, recorded with new sessions.
+///
+/// The IP address is taken from the connection, or from the `Forwarded`/`X-Forwarded-For`
+/// headers when `TRUST_PROXY_HEADERS` is set to `true` because the server is behind a proxy.
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>
+}
+
+impl FromRequest for ClientInfo {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let user_agent = req.headers()
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+
+        let trust_proxy = env::var("TRUST_PROXY_HEADERS").map(|value| value == "true").unwrap_or(false);
+        let ip_address = if trust_proxy {
+            req.connection_info().realip_remote_addr().map(|value| value.to_string())
+        }
+        else {
+            req.peer_addr().map(|addr| addr.ip().to_string())
+        };
+
+        ready(Ok(ClientInfo { user_agent, ip_address }))
+    }
+}
+
+/// Reads the session id from the session cookie, falling back to the `Authorization: Bearer` header.
+fn session_id_from_request(req: &HttpRequest) -> Option<uuid::Uuid> {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        if let Ok(session_id) = uuid::Uuid::parse_str(cookie.value()) {
+            return Some(session_id);
+        }
+    }
+
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let token = header.strip_prefix("Bearer ")?;
+    uuid::Uuid::parse_str(token.trim()).ok()
+}
+
+/// Builds the HttpOnly cookie holding a new session, expiring with the session.
+///
+/// The cookie is marked `Secure` unless `SESSION_COOKIE_SECURE` is set to `false`.
+pub fn session_cookie(session: &Session) -> Cookie<'static> {
+    let remaining = session.expires_at - chrono::Utc::now().naive_utc();
+    let cookie = Cookie::build(SESSION_COOKIE, session.id.to_string())
+        .path("/")
+        .http_only(true)
+        .secure(cookie_secure())
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(remaining.num_seconds()))
+        .finish();
+    cookie
+}
+
+/// Builds a cookie that removes the session cookie from the browser.
+pub fn removal_cookie() -> Cookie<'static> {
+    let mut cookie = Cookie::build(SESSION_COOKIE, "")
+        .path("/")
+        .http_only(true)
+        .secure(cookie_secure())
+        .same_site(SameSite::Lax)
+        .finish();
+    cookie.make_removal();
+    cookie
+}
+
+fn cookie_secure() -> bool {
+    env::var("SESSION_COOKIE_SECURE")
+        .map(|value| value != "false")
+        .unwrap_or(true)
+}
\ No newline at end of file
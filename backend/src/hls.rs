@@ -0,0 +1,194 @@
+use std::{fmt::Write, io::Cursor};
+
+use actix_web::web;
+use hound::WavReader;
+
+use crate::{
+    encoding::Codec,
+    error::AppError,
+    models::{Rendition, SongSegment, Songs},
+    encoding::{flac_to_wav, mp3_segment_start, wav_segment_to_mp3},
+    samples::{get_sample_from_bucket, wav_duration, SEGMENT_SECONDS},
+    storage::Storage
+};
+
+/// Owner of the ID3 `PRIV` frame that tells HLS players where a packed audio segment starts.
+const TIMESTAMP_OWNER: &[u8] = b"com.apple.streaming.transportStreamTimestamp\0";
+
+/// Bitrate lossless renditions are encoded at when they are streamed with HLS, which has no lossless packed audio format.
+pub const LOSSLESS_HLS_BITRATE_KBPS: u32 = 320;
+
+/// Codec of MP3 streams in the `CODECS` attribute of a master playlist.
+const MP3_CODECS: &str = "mp4a.40.34";
+
+/// The sample rate of a song whose segments were recorded when it was added.
+fn recorded_sample_rate(song: &Songs, segments: &[SongSegment]) -> Option<f64> {
+    match song.sample_rate {
+        Some(sample_rate) if sample_rate > 0 && !segments.is_empty() => Some(sample_rate as f64),
+        _ => None
+    }
+}
+
+/// Gets the duration in seconds of each of a song's segments.
+///
+/// Songs whose segments were recorded when they were added use their exact lengths. For other songs every
+/// segment is [`SEGMENT_SECONDS`] long except the last, whose length is read from the stored sample
+/// for lossless renditions, and worked out from the song's duration for encoded ones.
+pub async fn segment_durations(storage: &dyn Storage, song: &Songs, segments: &[SongSegment], rendition: &Rendition) -> Result<Vec<f64>, AppError> {
+    if let Some(sample_rate) = recorded_sample_rate(song, segments) {
+        return Ok(segments.iter().map(|segment| segment.frames as f64 / sample_rate).collect());
+    }
+    if song.num_samples <= 0 {
+        return Ok(Vec::new());
+    }
+    let last = (song.num_samples - 1) as u32;
+    let last_duration = match rendition.codec {
+        Codec::Wav => {
+            let last_sample = get_sample_from_bucket(storage, rendition, last).await?;
+            wav_duration(last_sample)?
+        },
+        Codec::Flac => {
+            let last_sample = get_sample_from_bucket(storage, rendition, last).await?;
+            wav_duration(flac_to_wav(last_sample)?)?
+        },
+        _ => {
+            let remaining_ms = song.duration_ms - (last * SEGMENT_SECONDS * 1000) as i32;
+            remaining_ms.clamp(1000, SEGMENT_SECONDS as i32 * 1000) as f64 / 1000.0
+        }
+    };
+
+    let mut durations = vec![SEGMENT_SECONDS as f64; last as usize];
+    durations.push(last_duration);
+    Ok(durations)
+}
+
+/// Gets the time in seconds at which one of a song's segments starts in its MP3 stream.
+///
+/// The segments of songs whose segments were recorded when they were added are cut from one MP3 stream,
+/// so they start on the frame the stream was cut at.
+pub fn segment_start(song: &Songs, segments: &[SongSegment], sample_number: u32) -> f64 {
+    let recorded = recorded_sample_rate(song, segments).zip(segments.get(sample_number as usize));
+    match recorded {
+        Some((sample_rate, segment)) => mp3_segment_start(segment.start_frame as u64, sample_rate as u32),
+        None => (sample_number * SEGMENT_SECONDS) as f64
+    }
+}
+
+/// Encodes a segment of a lossless rendition to MP3 as it is requested, since HLS has no lossless packed audio format.
+///
+/// The segments either side of it are fetched too, so it joins up with them without gaps like the segments of MP3 renditions.
+pub async fn lossless_segment_to_mp3(storage: &dyn Storage, song: &Songs, segments: &[SongSegment], rendition: &Rendition, sample_number: u32) -> Result<Vec<u8>, AppError> {
+    let sample = get_sample_from_bucket(storage, rendition, sample_number).await?;
+    let previous = match sample_number {
+        0 => None,
+        _ => Some(get_sample_from_bucket(storage, rendition, sample_number - 1).await?)
+    };
+    let next = match sample_number + 1 < song.num_samples.max(0) as u32 {
+        true => Some(get_sample_from_bucket(storage, rendition, sample_number + 1).await?),
+        false => None
+    };
+    let start = recorded_sample_rate(song, segments)
+        .and(segments.get(sample_number as usize))
+        .map(|segment| segment.start_frame as u64);
+
+    let codec = rendition.codec;
+    web::block(move || {
+        let as_wav = |sample: Vec<u8>| match codec {
+            Codec::Flac => flac_to_wav(sample),
+            _ => Ok(sample)
+        };
+        let sample = as_wav(sample)?;
+        let previous = previous.map(as_wav).transpose()?;
+        let next = next.map(as_wav).transpose()?;
+        let start = match start {
+            Some(start) => start,
+            // The segments of songs added before they were recorded are all the same length
+            None => match WavReader::new(Cursor::new(&sample)) {
+                Ok(reader) => (sample_number * SEGMENT_SECONDS) as u64 * reader.spec().sample_rate as u64,
+                Err(_) => return Err(AppError::Internal("Error opening sample".to_string()))
+            }
+        };
+        wav_segment_to_mp3(previous, sample, next, start, LOSSLESS_HLS_BITRATE_KBPS)
+    }).await?
+}
+
+/// Builds the HLS master playlist for a song, listing a media playlist at `{rendition}/playlist.m3u8` for each rendition
+/// so players can switch between them on segment boundaries.
+pub fn master_playlist(renditions: &[Rendition]) -> String {
+    let mut variants: Vec<(u32, &str)> = renditions.iter().map(|rendition| {
+        let bitrate_kbps = match rendition.codec {
+            Codec::Wav | Codec::Flac => LOSSLESS_HLS_BITRATE_KBPS,
+            _ => rendition.bitrate_kbps.unwrap_or(LOSSLESS_HLS_BITRATE_KBPS as i32) as u32
+        };
+        (bitrate_kbps, rendition.name.as_str())
+    }).collect();
+    variants.sort();
+
+    let mut playlist = String::new();
+    playlist.push_str("#EXTM3U\n");
+    playlist.push_str("#EXT-X-VERSION:3\n");
+    playlist.push_str("#EXT-X-INDEPENDENT-SEGMENTS\n");
+    for (bitrate_kbps, name) in variants {
+        let _ = writeln!(playlist, "#EXT-X-STREAM-INF:BANDWIDTH={},CODECS=\"{}\"", bitrate_kbps * 1000, MP3_CODECS);
+        let _ = writeln!(playlist, "{}/playlist.m3u8", name);
+    }
+    playlist
+}
+
+/// Builds the HLS media playlist for a rendition, with segments named `{n}.mp3` relative to the playlist.
+pub fn media_playlist(segment_durations: &[f64]) -> String {
+    // The target duration has to be at least every segment's duration rounded to the nearest second
+    let target_duration = segment_durations
+        .iter()
+        .map(|duration| duration.round() as u64)
+        .max()
+        .unwrap_or(SEGMENT_SECONDS as u64)
+        .max(1);
+
+    let mut playlist = String::new();
+    playlist.push_str("#EXTM3U\n");
+    playlist.push_str("#EXT-X-VERSION:3\n");
+    let _ = writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target_duration);
+    playlist.push_str("#EXT-X-MEDIA-SEQUENCE:0\n");
+    playlist.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n");
+    playlist.push_str("#EXT-X-INDEPENDENT-SEGMENTS\n");
+    for (i, duration) in segment_durations.iter().enumerate() {
+        let _ = writeln!(playlist, "#EXTINF:{:.3},", duration);
+        let _ = writeln!(playlist, "{}.mp3", i);
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    playlist
+}
+
+/// Turns an MP3 segment into an HLS packed audio segment by prefixing the ID3 tag
+/// holding the time, in seconds, at which the segment starts.
+pub fn packed_audio_segment(mp3_bytes: Vec<u8>, start_seconds: f64) -> Vec<u8> {
+    // Timestamps use the 90kHz MPEG-TS clock and wrap at 33 bits
+    let timestamp = ((start_seconds * 90_000.0) as u64) & 0x1_FFFF_FFFF;
+
+    let mut frame = Vec::with_capacity(TIMESTAMP_OWNER.len() + 8);
+    frame.extend_from_slice(TIMESTAMP_OWNER);
+    frame.extend_from_slice(&timestamp.to_be_bytes());
+
+    let mut segment = Vec::with_capacity(20 + frame.len() + mp3_bytes.len());
+    // ID3v2.4 header
+    segment.extend_from_slice(b"ID3\x04\x00\x00");
+    segment.extend_from_slice(&syncsafe(10 + frame.len() as u32));
+    // PRIV frame
+    segment.extend_from_slice(b"PRIV");
+    segment.extend_from_slice(&syncsafe(frame.len() as u32));
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(&frame);
+    segment.extend_from_slice(&mp3_bytes);
+    segment
+}
+
+/// Encodes a size as an ID3 syncsafe integer, using 7 bits per byte.
+fn syncsafe(size: u32) -> [u8; 4] {
+    [
+        ((size >> 21) & 0x7f) as u8,
+        ((size >> 14) & 0x7f) as u8,
+        ((size >> 7) & 0x7f) as u8,
+        (size & 0x7f) as u8
+    ]
+}
\ No newline at end of file
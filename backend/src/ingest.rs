@@ -0,0 +1,195 @@
+use std::{
+    collections::VecDeque,
+    env,
+    future::Future,
+    path::PathBuf
+};
+
+use actix_multipart::Field;
+use actix_web::web;
+use dotenvy::dotenv;
+use futures::StreamExt;
+use tempfile::NamedTempFile;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+use crate::{
+    decoding::UploadDecoder,
+    encoding::{SegmentFormat, SongEncoder},
+    error::AppError,
+    samples::{sample_checksum, segment_count, segment_key, split_samples, SampleConversion},
+    storage::Storage
+};
+
+/// Number of encoded samples that can wait to be uploaded while the next ones are encoded.
+const SAMPLES_IN_FLIGHT: usize = 2;
+
+/// Limits and locations for ingesting uploaded songs.
+#[derive(Clone, Debug)]
+pub struct IngestConfig {
+    /// The largest audio file accepted in an upload, in bytes
+    pub max_upload_bytes: u64,
+    /// Directory uploads are spooled to while they are processed
+    pub spool_dir: PathBuf
+}
+
+/// Reads the ingest limits from the environment.
+///
+/// `MAX_UPLOAD_MB` is the largest upload accepted in megabytes (defaults to 1024), and `UPLOAD_SPOOL_DIR`
+/// is where uploads are written while they are processed (defaults to the system's temporary directory).
+pub fn ingest_config_from_env() -> IngestConfig {
+    dotenv().ok();
+
+    let max_upload_mb = env::var("MAX_UPLOAD_MB").unwrap_or("1024".to_string());
+    let max_upload_mb: u64 = match max_upload_mb.parse() {
+        Ok(max_upload_mb) if max_upload_mb > 0 => max_upload_mb,
+        _ => panic!("Invalid MAX_UPLOAD_MB: {}", max_upload_mb)
+    };
+    let spool_dir = match env::var("UPLOAD_SPOOL_DIR") {
+        Ok(spool_dir) => PathBuf::from(spool_dir),
+        Err(_) => env::temp_dir()
+    };
+
+    IngestConfig { max_upload_bytes: max_upload_mb * 1024 * 1024, spool_dir }
+}
+
+impl IngestConfig {
+    /// The error for an upload over the maximum size.
+    pub fn too_large(&self) -> AppError {
+        AppError::PayloadTooLarge(format!("Uploads can be at most {} MB", self.max_upload_bytes / 1024 / 1024))
+    }
+}
+
+/// Writes a multipart field to a file in the spool directory as it arrives, rejecting it once it is over the maximum upload size.
+///
+/// The file is deleted when the returned handle is dropped.
+pub async fn spool_field(field: &mut Field, config: &IngestConfig) -> Result<NamedTempFile, AppError> {
+    if tokio::fs::create_dir_all(&config.spool_dir).await.is_err() {
+        return Err(AppError::Internal("Error creating upload directory".to_string()));
+    }
+    let spooled = match NamedTempFile::new_in(&config.spool_dir) {
+        Ok(spooled) => spooled,
+        Err(_) => return Err(AppError::Internal("Error creating upload file".to_string()))
+    };
+    let file = match spooled.reopen() {
+        Ok(file) => file,
+        Err(_) => return Err(AppError::Internal("Error creating upload file".to_string()))
+    };
+    let mut file = tokio::fs::File::from_std(file);
+
+    let mut size: u64 = 0;
+    while let Some(chunk) = field.next().await {
+        let data = match chunk {
+            Ok(data) => data,
+            Err(_) => return Err(AppError::Validation("Error reading upload".to_string()))
+        };
+        size += data.len() as u64;
+        if size > config.max_upload_bytes {
+            return Err(config.too_large());
+        }
+        if file.write_all(&data).await.is_err() {
+            return Err(AppError::Internal("Error writing upload file".to_string()));
+        }
+    }
+    if file.flush().await.is_err() {
+        return Err(AppError::Internal("Error writing upload file".to_string()));
+    }
+
+    Ok(spooled)
+}
+
+/// Audio that has been split into samples and uploaded.
+pub struct IngestedAudio {
+    pub frames: u64,
+    pub sample_rate: u32,
+    pub num_samples: u32,
+    /// The number of frames in each sample
+    pub segment_frames: Vec<u32>,
+    /// The size in bytes of each sample in each format, in the order the formats were given
+    pub sample_sizes: Vec<Vec<u32>>,
+    /// The checksum of each sample in each format, see [`sample_checksum`]
+    pub sample_checksums: Vec<Vec<String>>
+}
+
+/// Decodes a spooled upload and splits it into samples, encoding each one in every format and uploading it
+/// under the format's key prefix as soon as it's ready, so memory use doesn't grow with the length of the song.
+///
+/// `on_progress` is called after each sample is uploaded with the percentage of the song done so far,
+/// when the length of the song is known up front.
+pub async fn ingest_upload<P, F>(path: PathBuf, conversion: SampleConversion, formats: &[(SegmentFormat, String)], storage: &dyn Storage, mut on_progress: P) -> Result<IngestedAudio, AppError>
+where
+    P: FnMut(Option<u32>) -> F,
+    F: Future<Output = Result<(), AppError>>
+{
+    let (sender, mut receiver) = mpsc::channel::<(Vec<(Vec<u8>, String)>, u32, Option<u32>)>(SAMPLES_IN_FLIGHT);
+
+    // Decoding and encoding run on a blocking thread, handing each sample over once it's encoded in every format
+    let encode_formats: Vec<SegmentFormat> = formats.iter().map(|(format, _)| *format).collect();
+    let worker = web::block(move || -> Result<(u64, u32), AppError> {
+        let mut decoder = UploadDecoder::open(&path)?;
+        let sample_rate = decoder.sample_rate();
+        let total_samples = decoder.total_frames()
+            .filter(|total| *total > 0)
+            .map(|total| segment_count(total, sample_rate, conversion.segment_seconds));
+        let mut encoders: Vec<SongEncoder> = encode_formats.iter()
+            .map(|format| format.song_encoder(decoder.channels(), sample_rate))
+            .collect::<Result<_, _>>()?;
+        // Samples each format has finished, and the length of each sample, until it is finished in every format
+        let mut encoded: Vec<VecDeque<Vec<u8>>> = vec![VecDeque::new(); encoders.len()];
+        let mut pending_frames: VecDeque<u32> = VecDeque::new();
+        let mut samples_done = 0;
+        let mut send_done = |encoded: &mut Vec<VecDeque<Vec<u8>>>, pending_frames: &mut VecDeque<u32>| -> Result<(), AppError> {
+            while !encoded.is_empty() && encoded.iter().all(|samples| !samples.is_empty()) {
+                let samples: Vec<(Vec<u8>, String)> = encoded.iter_mut()
+                    .filter_map(VecDeque::pop_front)
+                    .map(|sample| {
+                        let checksum = sample_checksum(&sample);
+                        (sample, checksum)
+                    })
+                    .collect();
+                let sample_frames = pending_frames.pop_front().unwrap_or(0);
+                // The song isn't done until every sample is uploaded, however far the decoder has got
+                samples_done += 1;
+                let percent = total_samples.map(|total| (samples_done * 100 / total).min(99) as u32);
+                if sender.blocking_send((samples, sample_frames, percent)).is_err() {
+                    // Uploading failed, so nothing is waiting for more samples
+                    return Err(AppError::Internal("Ingest was stopped".to_string()));
+                }
+            }
+            Ok(())
+        };
+
+        let frames = split_samples(&mut decoder, conversion, |sample, sample_frames| {
+            for (encoder, samples) in encoders.iter_mut().zip(encoded.iter_mut()) {
+                samples.extend(encoder.push(sample.clone())?);
+            }
+            pending_frames.push_back(sample_frames);
+            send_done(&mut encoded, &mut pending_frames)
+        })?;
+        for (encoder, samples) in encoders.into_iter().zip(encoded.iter_mut()) {
+            samples.extend(encoder.finish()?);
+        }
+        send_done(&mut encoded, &mut pending_frames)?;
+        if !pending_frames.is_empty() || encoded.iter().any(|samples| !samples.is_empty()) {
+            return Err(AppError::Internal("Error encoding sample".to_string()));
+        }
+        Ok((frames, sample_rate))
+    });
+
+    let mut num_samples = 0;
+    let mut segment_frames = Vec::new();
+    let mut sample_sizes = vec![Vec::new(); formats.len()];
+    let mut sample_checksums = vec![Vec::new(); formats.len()];
+    while let Some((encoded, sample_frames, percent)) = receiver.recv().await {
+        for (n, ((format, key_prefix), (sample, checksum))) in formats.iter().zip(encoded).enumerate() {
+            sample_sizes[n].push(sample.len() as u32);
+            sample_checksums[n].push(checksum);
+            storage.upload_file(&segment_key(key_prefix, format.codec, num_samples), sample).await?;
+        }
+        segment_frames.push(sample_frames);
+        num_samples += 1;
+        on_progress(percent).await?;
+    }
+    let (frames, sample_rate) = worker.await??;
+
+    Ok(IngestedAudio { frames, sample_rate, num_samples, segment_frames, sample_sizes, sample_checksums })
+}
\ No newline at end of file
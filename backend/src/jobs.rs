@@ -0,0 +1,379 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration
+};
+use actix_web::rt;
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql}
+};
+use dotenvy::dotenv;
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use crate::{
+    config::env_or,
+    db::{self, DbPool},
+    encoding::SegmentFormat,
+    error::AppError,
+    ingest::{ingest_upload, IngestedAudio},
+    models::{IngestJob, NewIngestJob},
+    samples::{cover_key, delete_song_objects, frames_to_ms, insert_renditions, insert_segment_sizes, insert_song_segments, rendition_key_prefix, SampleConversion, SongStatus},
+    schema::sql_types,
+    storage::Storage
+};
+
+/// The state of an ingest job, stored in the `job_status` postgres enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = sql_types::JobStatus)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Waiting for a worker, either for the first time or to be retried
+    Queued,
+    /// A worker is making the song's samples
+    Processing,
+    /// Every attempt failed, the song will never be ready
+    Failed,
+    /// The song's samples have all been made
+    Ready
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Processing => "processing",
+            JobStatus::Failed => "failed",
+            JobStatus::Ready => "ready"
+        }
+    }
+}
+
+impl ToSql<sql_types::JobStatus, Pg> for JobStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<sql_types::JobStatus, Pg> for JobStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"queued" => Ok(JobStatus::Queued),
+            b"processing" => Ok(JobStatus::Processing),
+            b"failed" => Ok(JobStatus::Failed),
+            b"ready" => Ok(JobStatus::Ready),
+            _ => Err("Unrecognized job status".into())
+        }
+    }
+}
+
+/// How ingest jobs are run and retried.
+#[derive(Clone, Copy, Debug)]
+pub struct JobConfig {
+    /// Number of jobs processed at the same time
+    pub workers: u64,
+    /// Number of times a job is attempted before it fails for good
+    pub max_attempts: i32,
+    /// How long a failed job waits before it is retried, multiplied by the number of attempts so far
+    pub retry_delay: chrono::Duration,
+    /// How often idle workers check for new jobs
+    pub poll_interval: Duration,
+    /// How long a job can go without reporting progress before it is assumed its worker died
+    pub stale_after: chrono::Duration
+}
+
+impl JobConfig {
+    /// Runs `INGEST_WORKERS` workers (default 2), attempting each job up to `INGEST_MAX_ATTEMPTS` times (default 3)
+    /// and waiting `INGEST_RETRY_DELAY_SECS` seconds (default 30) times the attempts so far between them.
+    /// Idle workers check for jobs every `INGEST_POLL_INTERVAL_SECS` seconds (default 2), and a job that hasn't reported
+    /// progress for `INGEST_STALE_AFTER_SECS` seconds (default 600) is taken over by another worker.
+    pub fn from_env() -> Self {
+        dotenv().ok();
+
+        JobConfig {
+            workers: env_or("INGEST_WORKERS", 2_u64).max(1),
+            max_attempts: env_or("INGEST_MAX_ATTEMPTS", 3_i32).max(1),
+            retry_delay: chrono::Duration::seconds(env_or("INGEST_RETRY_DELAY_SECS", 30)),
+            poll_interval: Duration::from_secs(env_or("INGEST_POLL_INTERVAL_SECS", 2_u64).max(1)),
+            stale_after: chrono::Duration::seconds(env_or("INGEST_STALE_AFTER_SECS", 600_i64).max(1))
+        }
+    }
+}
+
+/// Queues a job to make the samples of a staged song from its spooled upload, on behalf of the user who uploaded it.
+pub fn enqueue_job(conn: &mut PgConnection, song_id: &uuid::Uuid, upload_path: &str, created_by: &uuid::Uuid) -> Result<IngestJob, AppError> {
+    use crate::schema::ingest_jobs;
+
+    let job = diesel::insert_into(ingest_jobs::table)
+        .values(&NewIngestJob { song_id, upload_path, created_by })
+        .returning(IngestJob::as_returning())
+        .get_result(conn)?;
+
+    Ok(job)
+}
+
+pub fn get_job(conn: &mut PgConnection, job_id: &uuid::Uuid) -> Result<IngestJob, AppError> {
+    use crate::schema::ingest_jobs::dsl::*;
+
+    let response = ingest_jobs.filter(id.eq(job_id)).select(IngestJob::as_select()).first(conn).optional()?;
+    match response {
+        Some(response) => Ok(response),
+        None => Err(AppError::NotFound("Job not found".to_string()))
+    }
+}
+
+/// Takes the oldest job that is due to run, marking it as processing.
+///
+/// Jobs left processing by a worker that stopped reporting progress are taken over, or failed
+/// if they are out of attempts. Failed jobs are returned with their new status so the worker can
+/// clean up after them. Rows are locked while they are claimed so two workers, even on
+/// different servers, never take the same job.
+pub fn claim_job(conn: &mut PgConnection, config: &JobConfig) -> Result<Option<IngestJob>, AppError> {
+    use crate::schema::ingest_jobs::dsl::*;
+
+    conn.transaction(|conn| {
+        let now = chrono::Utc::now().naive_utc();
+        let due = status.eq(JobStatus::Queued).and(run_after.le(now));
+        let stale = status.eq(JobStatus::Processing).and(updated_at.lt(now - config.stale_after));
+        let job = ingest_jobs
+            .filter(due.or(stale))
+            .order(created_at.asc())
+            .select(IngestJob::as_select())
+            .for_update()
+            .skip_locked()
+            .first(conn)
+            .optional()?;
+        let job = match job {
+            Some(job) => job,
+            None => return Ok(None)
+        };
+
+        if job.attempts >= config.max_attempts {
+            let job = diesel::update(ingest_jobs.filter(id.eq(job.id)))
+                .set((status.eq(JobStatus::Failed), error.eq("The worker processing the song stopped"), updated_at.eq(now)))
+                .returning(IngestJob::as_returning())
+                .get_result(conn)?;
+            return Ok(Some(job));
+        }
+
+        let job = diesel::update(ingest_jobs.filter(id.eq(job.id)))
+            .set((status.eq(JobStatus::Processing), attempts.eq(attempts + 1), progress.eq(0), updated_at.eq(now)))
+            .returning(IngestJob::as_returning())
+            .get_result(conn)?;
+
+        Ok(Some(job))
+    })
+}
+
+/// Records how far an attempt at a job has got, which also shows its worker is still alive.
+///
+/// Fails with a conflict if the job is no longer being processed by this attempt, because it was
+/// cancelled or taken over by another worker, so the worker can stop.
+pub fn update_job_progress(conn: &mut PgConnection, job_id: &uuid::Uuid, attempt: i32, percent: Option<u32>) -> Result<(), AppError> {
+    use crate::schema::ingest_jobs::dsl::*;
+
+    let now = chrono::Utc::now().naive_utc();
+    let job = ingest_jobs.filter(id.eq(job_id)).filter(status.eq(JobStatus::Processing)).filter(attempts.eq(attempt));
+    let updated = match percent {
+        Some(percent) => diesel::update(job)
+            .set((progress.eq(percent as i32), updated_at.eq(now)))
+            .execute(conn)?,
+        None => diesel::update(job)
+            .set(updated_at.eq(now))
+            .execute(conn)?
+    };
+    if updated == 0 {
+        return Err(AppError::Conflict("The job is no longer being processed".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Fails a song's unfinished jobs when it is deleted, returning the spooled uploads of the ones no worker has taken.
+/// Workers delete the uploads of the jobs they are processing themselves.
+pub fn cancel_song_jobs(conn: &mut PgConnection, arg_song_id: &uuid::Uuid) -> Result<Vec<String>, AppError> {
+    use crate::schema::ingest_jobs::dsl::*;
+
+    let queued: Vec<String> = ingest_jobs
+        .filter(song_id.eq(arg_song_id))
+        .filter(status.eq(JobStatus::Queued))
+        .select(upload_path)
+        .load(conn)?;
+    diesel::update(ingest_jobs.filter(song_id.eq(arg_song_id)).filter(status.eq_any([JobStatus::Queued, JobStatus::Processing])))
+        .set((status.eq(JobStatus::Failed), error.eq("The song was deleted"), updated_at.eq(chrono::Utc::now().naive_utc())))
+        .execute(conn)?;
+
+    Ok(queued)
+}
+
+/// Marks a job's song as ready with the length of the audio and the renditions its samples were stored in.
+///
+/// Fails with a conflict if the song is no longer staged, because it was deleted while it was processed.
+pub fn complete_job(conn: &mut PgConnection, job: &IngestJob, audio: &IngestedAudio, formats: &[SegmentFormat]) -> Result<(), AppError> {
+    use crate::schema::{ingest_jobs, songs};
+
+    conn.transaction(|conn| {
+        let updated = diesel::update(songs::table.filter(songs::id.eq(job.song_id)).filter(songs::status.eq(SongStatus::Staged)))
+            .set((
+                songs::duration_ms.eq(frames_to_ms(audio.frames, audio.sample_rate) as i32),
+                songs::num_samples.eq(audio.num_samples as i32),
+                songs::sample_rate.eq(audio.sample_rate as i32),
+                songs::lossless.eq(formats.iter().any(|format| format.codec.is_lossless())),
+                songs::status.eq(SongStatus::Ready)
+            ))
+            .execute(conn)?;
+        if updated == 0 {
+            return Err(AppError::Conflict("The song was deleted while it was processed".to_string()));
+        }
+        insert_song_segments(conn, &job.song_id, &audio.segment_frames)?;
+        let renditions = insert_renditions(conn, &job.song_id, formats)?;
+        for ((format, sizes), checksums) in formats.iter().zip(&audio.sample_sizes).zip(&audio.sample_checksums) {
+            let key_prefix = rendition_key_prefix(&job.song_id, format);
+            if let Some(rendition) = renditions.iter().find(|rendition| rendition.key_prefix == key_prefix) {
+                insert_segment_sizes(conn, &rendition.id, sizes, checksums)?;
+            }
+        }
+        diesel::update(ingest_jobs::table.filter(ingest_jobs::id.eq(job.id)))
+            .set((
+                ingest_jobs::status.eq(JobStatus::Ready),
+                ingest_jobs::progress.eq(100),
+                ingest_jobs::error.eq(None::<String>),
+                ingest_jobs::updated_at.eq(chrono::Utc::now().naive_utc())
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    })
+}
+
+/// Records why a job failed, queueing it to be tried again later if it can be.
+/// Returns the job's new status, or `None` if it was cancelled or taken over by another worker in the meantime.
+pub fn fail_job(conn: &mut PgConnection, job: &IngestJob, message: &str, retry: bool, config: &JobConfig) -> Result<Option<JobStatus>, AppError> {
+    use crate::schema::ingest_jobs::dsl::*;
+
+    let now = chrono::Utc::now().naive_utc();
+    let new_status = match retry && job.attempts < config.max_attempts {
+        true => JobStatus::Queued,
+        false => JobStatus::Failed
+    };
+    let updated = diesel::update(ingest_jobs.filter(id.eq(job.id)).filter(status.eq(JobStatus::Processing)).filter(attempts.eq(job.attempts)))
+        .set((
+            status.eq(new_status),
+            error.eq(message),
+            run_after.eq(now + config.retry_delay * job.attempts),
+            updated_at.eq(now)
+        ))
+        .execute(conn)?;
+
+    match updated {
+        0 => Ok(None),
+        _ => Ok(Some(new_status))
+    }
+}
+
+/// Spawns the workers that make the samples of queued songs.
+///
+/// Each worker takes a job, ingests its spooled upload in every format and marks the song as ready,
+/// reporting progress as it goes. Uploads are deleted once their job is done or has failed for good,
+/// along with the samples of songs that will never be ready.
+pub fn spawn_ingest_workers(pool: DbPool, storage: Arc<dyn Storage>, formats: Vec<SegmentFormat>, conversion: SampleConversion, config: JobConfig) {
+    for _ in 0..config.workers {
+        let pool = pool.clone();
+        let storage = storage.clone();
+        let formats = formats.clone();
+        rt::spawn(async move {
+            loop {
+                let claimed = db::run(&pool, move |conn| claim_job(conn, &config)).await;
+                match claimed {
+                    Ok(Some(job)) if job.status == JobStatus::Failed => clean_up_job(storage.as_ref(), &job, JobStatus::Failed).await,
+                    Ok(Some(job)) => run_job(&pool, storage.as_ref(), &formats, conversion, &config, job).await,
+                    Ok(None) => rt::time::sleep(config.poll_interval).await,
+                    Err(err) => {
+                        println!("Error claiming ingest job: {}", err);
+                        rt::time::sleep(config.poll_interval).await;
+                    }
+                }
+            }
+        });
+    }
+}
+
+async fn run_job(pool: &DbPool, storage: &dyn Storage, formats: &[SegmentFormat], conversion: SampleConversion, config: &JobConfig, job: IngestJob) {
+    println!("Processing song {} (attempt {})", job.song_id, job.attempts);
+    let renditions: Vec<(SegmentFormat, String)> = formats.iter().map(|format| (*format, rendition_key_prefix(&job.song_id, format))).collect();
+    let (job_id, attempt) = (job.id, job.attempts);
+    // Ingesting stops as soon as the job is cancelled or taken over, since the progress can't be recorded
+    let result = ingest_upload(PathBuf::from(&job.upload_path), conversion, &renditions, storage, |percent| {
+        let pool = pool.clone();
+        async move { db::run(&pool, move |conn| update_job_progress(conn, &job_id, attempt, percent)).await }
+    }).await;
+
+    let result = match result {
+        Ok(audio) => {
+            let formats = formats.to_vec();
+            let completed_job = job.clone();
+            let completed = db::run(pool, move |conn| complete_job(conn, &completed_job, &audio, &formats)).await;
+            completed.map(|_| JobStatus::Ready)
+        },
+        Err(err) => Err(err)
+    };
+    let status = match result {
+        Ok(status) => Ok(Some(status)),
+        Err(err) => {
+            println!("Error processing song {}: {}", job.song_id, err);
+            // An upload that can't be decoded or a song that has been deleted will never work, anything else might
+            let retry = !matches!(err, AppError::Validation(_) | AppError::Conflict(_));
+            let message = err.message().to_string();
+            let failed_job = job.clone();
+            let config = *config;
+            db::run(pool, move |conn| fail_job(conn, &failed_job, &message, retry, &config)).await
+        }
+    };
+
+    match status {
+        Ok(Some(status)) => clean_up_job(storage, &job, status).await,
+        Ok(None) => {
+            // A cancelled job is cleaned up by its worker, one that was taken over belongs to the new worker
+            let current = db::run(pool, move |conn| get_job(conn, &job_id)).await;
+            let cancelled = match current {
+                Ok(current) => current.status == JobStatus::Failed && current.attempts == attempt,
+                // Deleting a song deletes its jobs
+                Err(AppError::NotFound(_)) => true,
+                Err(err) => {
+                    println!("Error getting ingest job {}: {}", job.id, err);
+                    return;
+                }
+            };
+            match cancelled {
+                true => {
+                    println!("Stopped processing song {}, its job was cancelled", job.song_id);
+                    clean_up_job(storage, &job, JobStatus::Failed).await
+                },
+                false => println!("Stopped processing song {}, its job was taken over", job.song_id)
+            }
+        },
+        // The job is left processing, and is retried once it is seen to be stale
+        Err(err) => println!("Error updating ingest job {}: {}", job.id, err)
+    }
+}
+
+/// Deletes the upload of a job that is done or has failed for good, and the samples of one that failed.
+async fn clean_up_job(storage: &dyn Storage, job: &IngestJob, status: JobStatus) {
+    if status == JobStatus::Failed {
+        // Samples made before the job failed will never be used, the cover is kept until the song is deleted
+        if let Err(err) = delete_song_objects(storage, &job.song_id, &[cover_key(&job.song_id)]).await {
+            println!("Error deleting samples of song {}: {}", job.song_id, err);
+        }
+    }
+    if matches!(status, JobStatus::Ready | JobStatus::Failed) && tokio::fs::remove_file(&job.upload_path).await.is_err() {
+        println!("Error deleting upload {}", job.upload_path);
+    }
+}
\ No newline at end of file
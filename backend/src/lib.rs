@@ -9,18 +9,42 @@ use flate2::{
     Compression
 };
 
+pub mod error;
+pub mod config;
 pub mod models;
 pub mod schema;
 pub mod db;
 pub mod spaces;
+pub mod storage;
 pub mod auth;
+pub mod extractors;
 pub mod samples;
+pub mod roles;
+pub mod tasks;
+pub mod password_reset;
+pub mod notifier;
+pub mod rate_limit;
+pub mod encoding;
+pub mod decoding;
+pub mod ingest;
+pub mod jobs;
+pub mod reconcile;
+pub mod hls;
+pub mod streaming;
+pub mod seek;
+pub mod manifest;
+pub mod tags;
+pub mod cors;
 
-pub fn compress_data(data: Vec<u8>) -> Vec<u8> {
+pub fn compress_data(data: Vec<u8>) -> Result<Vec<u8>, error::AppError> {
     let mut e = ZlibEncoder::new(Vec::new(), Compression::new(6));
-    e.write_all(&data).unwrap();
-    let compressed_data = e.finish().unwrap();
-    return compressed_data;
+    if e.write_all(&data).is_err() {
+        return Err(error::AppError::Internal("Error compressing data".to_string()));
+    }
+    match e.finish() {
+        Ok(compressed) => Ok(compressed),
+        Err(_) => Err(error::AppError::Internal("Error compressing data".to_string()))
+    }
 }
 
 #[derive(Deserialize)]
@@ -29,20 +53,78 @@ pub struct PostedUser {
     pub password: String,
 }
 
+/// Body of a successful login or sign up. The session id is only sent in the HttpOnly cookie.
 #[derive(Serialize, Deserialize)]
 pub struct SessionReturn {
-    pub session_id: uuid::Uuid,
     pub error: String
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct SessionInput {
-    pub session_id: uuid::Uuid
-}
-
 #[derive(Serialize)]
 pub struct UserResponse {
     pub id: uuid::Uuid,
     pub username: String,
-    pub permissions: String
+    pub role: roles::Role,
+    pub capabilities: &'static [roles::Capability],
+    pub locked_until: Option<chrono::NaiveDateTime>
+}
+
+impl UserResponse {
+    /// Describes users along with when their usernames' lockouts end, if they are locked out.
+    pub fn load(conn: &mut diesel::PgConnection, users: Vec<models::Users>) -> Result<Vec<UserResponse>, error::AppError> {
+        let usernames: Vec<String> = users.iter().map(|user| user.username.clone()).collect();
+        let mut lockouts = rate_limit::get_lockouts(conn, &usernames)?;
+        let responses = users.into_iter().map(|user| UserResponse {
+            id: user.id,
+            locked_until: lockouts.remove(&user.username),
+            username: user.username,
+            role: user.role,
+            capabilities: user.role.capabilities()
+        });
+        Ok(responses.collect())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RenditionQuery {
+    pub rendition: Option<String>,
+    pub format: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct SeekQuery {
+    pub position_ms: u64
+}
+
+#[derive(Deserialize)]
+pub struct RoleInput {
+    pub role: roles::Role
+}
+
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub id: uuid::Uuid,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_seen: chrono::NaiveDateTime,
+    pub expires_at: chrono::NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// Whether this is the session making the request
+    pub current: bool
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordInput {
+    pub current_password: String,
+    pub new_password: String
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    pub username: String
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetInput {
+    pub token: String,
+    pub new_password: String
 }
\ No newline at end of file
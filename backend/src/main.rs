@@ -2,189 +2,573 @@ use std::{
     collections::HashMap,
     io::Cursor
 };
-use actix_cors::Cors;
 use actix_web::{
-    delete, get, post, web, App, HttpResponse, HttpServer, Responder
+    delete, error, get, http::header::{self, Accept}, post, put, route, web, App, HttpResponse, HttpServer
 };
 use backend::{
     auth::{
+        change_password,
         create_session,
         create_user,
-        get_user,
+        get_user_by_username,
+        get_user_sessions,
+        get_users,
         invalidate_session,
+        prepare_dummy_hash,
+        revoke_other_sessions,
+        revoke_user_session,
+        set_user_role,
         valid_session,
         verify_user
     }, compress_data,
-    db::establish_connection,
-    models::NewSong,
+    cors::{
+        allowed_origins_from_env,
+        cors
+    },
+    ingest::{
+        ingest_config_from_env,
+        spool_field,
+        IngestConfig
+    },
+    jobs::{
+        enqueue_job,
+        get_job,
+        spawn_ingest_workers,
+        JobConfig
+    },
+    extractors::{
+        removal_cookie,
+        session_cookie,
+        AuthenticatedUser,
+        ClientInfo,
+        SessionToken
+    },
+    db::{
+        self,
+        establish_pool,
+        DbPool
+    },
+    decoding::UploadDecoder,
+    encoding::{
+        renditions_from_env,
+        Codec,
+        SegmentFormat
+    },
+    error::AppError,
+    hls::{
+        lossless_segment_to_mp3,
+        master_playlist,
+        media_playlist,
+        packed_audio_segment,
+        segment_durations,
+        segment_start
+    },
+    manifest::get_song_info,
+    models::{
+        IngestJob,
+        NewSong,
+        Rendition,
+        Users
+    },
+    notifier::{
+        notifier_from_env,
+        Notifier
+    },
+    password_reset::{
+        create_reset_token,
+        reset_password
+    },
+    rate_limit::{
+        clear_failed_logins,
+        locked_out_for,
+        record_failed_login,
+        unlock_user,
+        LockoutPolicy,
+        RateLimiter
+    },
     samples::{
-        delete_song_from_server, get_all_samples, get_sample_from_bucket, get_song, get_songs_list, insert_song, mp3_to_wav
+        begin_song_deletion, choose_rendition, cover_key, delete_song_objects, delete_song_record, get_rendition, get_sample_from_bucket, get_segment_sizes, get_song, get_song_renditions, get_song_segments, get_songs_list, insert_segment_sizes, insert_song, negotiate_rendition, sample_conversion_from_env
+    },
+    seek::seek,
+    storage::{
+        storage_from_env,
+        Storage
+    },
+    streaming::{
+        can_stream,
+        measure_segment_sizes,
+        SongStream
     },
-    spaces::upload_file_to_bucket,
+    tags::{
+        read_tags,
+        SongTags
+    },
+    tasks::spawn_session_cleanup,
+    roles::{
+        Capability,
+        Role
+    },
+    ChangePasswordInput,
+    PasswordResetInput,
+    PasswordResetRequest,
     PostedUser,
-    SessionInput,
+    RenditionQuery,
+    RoleInput,
+    SeekQuery,
+    SessionResponse,
     SessionReturn,
     UserResponse
 };
 use actix_multipart::Multipart;
 use futures::stream::StreamExt;
 use futures::TryStreamExt;
-use hound::WavReader;
 use image::{ImageFormat, ImageReader};
+use diesel::Connection;
 
 #[get("/songs_list")]
-async fn songs_list() -> impl Responder {
-    let connection = &mut establish_connection();
-    
-    let songs_list = get_songs_list(connection).await;
-
-    let songs_list = match songs_list {
-        Ok(songs_list) => songs_list,
-        Err(_) => return HttpResponse::InternalServerError().body("Error loading songs list")
-    };
+async fn songs_list(pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let songs_list = db::run(&pool, get_songs_list).await?;
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .json(songs_list)
+        .json(songs_list))
 }
 
+/// Get a song's info and the manifest of its segments
 #[get("/song_info/{song_id}")]
-async fn song_info(path: web::Path<uuid::Uuid>) -> impl Responder {
+async fn song_info(path: web::Path<uuid::Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
     let song_id = path.into_inner();
-    let connection = &mut establish_connection();
-    let result = get_song(connection, &song_id).await;
-    let result = match result {
-        Ok(result) => result,
-        Err(error) => return HttpResponse::InternalServerError().body(error)
-    };
-    HttpResponse::Ok()
+    let result = db::run(&pool, move |conn| get_song_info(conn, &song_id)).await?;
+    Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .json(result)
+        .json(result))
 }
 
-/// Get a 10 second sample from a song compressed with zlib
+/// List the renditions a song can be streamed in
+#[get("/song_renditions/{song_id}")]
+async fn song_renditions(path: web::Path<uuid::Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let song_id = path.into_inner();
+    let renditions = db::run(&pool, move |conn| {
+        get_song(conn, &song_id)?;
+        get_song_renditions(conn, &song_id)
+    }).await?;
+    Ok(HttpResponse::Ok().json(renditions))
+}
+
+/// The codecs a client asked for with `?format=` or the `Accept` header, in order of preference,
+/// and whether it has to get one of them
+fn requested_codecs(format: Option<String>, accept: Option<web::Header<Accept>>) -> Result<(Vec<Codec>, bool), AppError> {
+    // An explicit format must be available, formats from the Accept header are only preferences
+    match format {
+        Some(format) => match Codec::from_name(&format) {
+            Some(codec) => Ok((vec![codec], true)),
+            None => Err(AppError::Validation(format!("Unknown sample format: {}", format)))
+        },
+        None => {
+            let accepted = accept.map(|accept| accept.into_inner().ranked()).unwrap_or_default();
+            let codecs = accepted.iter().filter_map(|mime| Codec::from_content_type(mime.essence_str())).collect();
+            Ok((codecs, false))
+        }
+    }
+}
+
+/// Get a 10 second sample from a song
+///
+/// The rendition can be chosen with `?rendition=`, or a format with `?format=` (such as `flac` or `mp3`)
+/// or the `Accept` header, otherwise the song's default rendition is used.
+/// Encoded samples are sent as they are stored, WAV samples are compressed with zlib.
 #[get("/sample_compressed/{song_id}/{sample_number}")]
-async fn samples_compressed_endpoint(path: web::Path<(uuid::Uuid, u32)>) -> impl Responder {
+async fn samples_compressed_endpoint(path: web::Path<(uuid::Uuid, u32)>, query: web::Query<RenditionQuery>, accept: Option<web::Header<Accept>>, storage: web::Data<dyn Storage>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
     let (song_id, sample_number) = path.into_inner();
+    let query = query.into_inner();
+    let (codecs, strict) = requested_codecs(query.format, accept)?;
+    let name = query.rendition;
+    let rendition = db::run(&pool, move |conn| match name {
+        Some(name) => get_rendition(conn, &song_id, Some(&name)),
+        None => negotiate_rendition(conn, &song_id, &codecs, strict)
+    }).await?;
 
-    let resp = get_sample_from_bucket(&song_id, sample_number).await;
-    let resp = match resp {
-        Ok(resp) => resp,
-        Err(error) => return HttpResponse::InternalServerError().body(error)
-    };
-    let resp = compress_data(resp);
+    let resp = get_sample_from_bucket(storage.get_ref(), &rendition, sample_number).await?;
+    if rendition.codec != Codec::Wav {
+        return Ok(HttpResponse::Ok()
+            .content_type(rendition.codec.content_type())
+            .insert_header((header::VARY, "Accept"))
+            .body(resp));
+    }
+    let resp = compress_data(resp)?;
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type("application/zlib")
-        .body(resp)
+        .insert_header((header::VARY, "Accept"))
+        .body(resp))
+}
+
+/// Get a whole song as one file that can be played and seeked with `Range` requests, like a file on disk
+///
+/// The rendition is chosen like for samples, out of the MP3 and WAV renditions as FLAC segments can't be joined.
+/// A single byte range is sent as 206 Partial Content, and anything else gets the whole song.
+#[route("/stream/{song_id}", method = "GET", method = "HEAD")]
+async fn stream_song(path: web::Path<uuid::Uuid>, query: web::Query<RenditionQuery>, accept: Option<web::Header<Accept>>, range: Option<web::Header<header::Range>>, storage: web::Data<dyn Storage>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let song_id = path.into_inner();
+    let query = query.into_inner();
+    let (codecs, strict) = requested_codecs(query.format, accept)?;
+    let name = query.rendition;
+    let (song, rendition, sizes) = db::run(&pool, move |conn| {
+        let song = get_song(conn, &song_id)?;
+        let renditions: Vec<Rendition> = get_song_renditions(conn, &song_id)?.into_iter().filter(|rendition| can_stream(rendition.codec)).collect();
+        let rendition = match name {
+            Some(name) => match renditions.into_iter().find(|rendition| rendition.name == name) {
+                Some(rendition) => rendition,
+                None => return Err(AppError::NotFound("Rendition not found or can't be streamed".to_string()))
+            },
+            None => choose_rendition(renditions, &codecs, strict)?
+        };
+        let sizes = get_segment_sizes(conn, &rendition.id)?;
+        Ok((song, rendition, sizes))
+    }).await?;
+
+    // Songs added before segment sizes were recorded have them measured once
+    let sizes: Vec<u32> = match sizes.len() == song.num_samples as usize {
+        true => sizes.iter().map(|segment| segment.byte_length as u32).collect(),
+        false => {
+            let sizes = measure_segment_sizes(storage.get_ref(), &rendition, song.num_samples as u32).await?;
+            let (rendition_id, recorded) = (rendition.id, sizes.clone());
+            db::run(&pool, move |conn| insert_segment_sizes(conn, &rendition_id, &recorded, &[])).await?;
+            sizes
+        }
+    };
+    let stream = SongStream::new(storage.get_ref(), &rendition, &sizes).await?;
+    let size = stream.size();
+    if size == 0 {
+        return Err(AppError::NotFound("Song has no audio".to_string()));
+    }
+
+    let requested = match range.map(|range| range.into_inner()) {
+        Some(header::Range::Bytes(ranges)) if ranges.len() == 1 => match ranges[0].to_satisfiable_range(size) {
+            Some(range) => Some(range),
+            None => return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish())
+        },
+        _ => None
+    };
+    let (start, end) = requested.unwrap_or((0, size - 1));
+
+    let mut response = match requested {
+        Some(_) => HttpResponse::PartialContent(),
+        None => HttpResponse::Ok()
+    };
+    response
+        .content_type(rendition.codec.content_type())
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::VARY, "Accept"))
+        .no_chunking(end - start + 1);
+    if requested.is_some() {
+        response.insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size)));
+    }
+    let body = stream.bytes(storage.into_inner(), start, end).map_err(error::Error::from);
+    Ok(response.streaming(body))
+}
+
+/// Find the sample a position in a song (`?position_ms=`) is in and how far into it the position is
+#[get("/seek/{song_id}")]
+async fn seek_song(path: web::Path<uuid::Uuid>, query: web::Query<SeekQuery>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let song_id = path.into_inner();
+    let position_ms = query.position_ms;
+    let position = db::run(&pool, move |conn| {
+        let song = get_song(conn, &song_id)?;
+        let segments = get_song_segments(conn, &song_id)?;
+        seek(&song, &segments, position_ms)
+    }).await?;
+    Ok(HttpResponse::Ok().json(position))
+}
+
+/// Get the HLS master playlist of a song, listing every rendition
+#[get("/hls/{song_id}/master.m3u8")]
+async fn hls_master_playlist(path: web::Path<uuid::Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let song_id = path.into_inner();
+    let renditions = db::run(&pool, move |conn| {
+        get_song(conn, &song_id)?;
+        get_song_renditions(conn, &song_id)
+    }).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.apple.mpegurl")
+        .body(master_playlist(&renditions)))
+}
+
+/// Get the HLS media playlist of one of a song's renditions
+#[get("/hls/{song_id}/{rendition}/playlist.m3u8")]
+async fn hls_playlist(path: web::Path<(uuid::Uuid, String)>, storage: web::Data<dyn Storage>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let (song_id, name) = path.into_inner();
+    let (song, segments, rendition) = db::run(&pool, move |conn| {
+        let song = get_song(conn, &song_id)?;
+        let segments = get_song_segments(conn, &song_id)?;
+        let rendition = get_rendition(conn, &song_id, Some(&name))?;
+        Ok((song, segments, rendition))
+    }).await?;
+    let durations = segment_durations(storage.get_ref(), &song, &segments, &rendition).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.apple.mpegurl")
+        .body(media_playlist(&durations)))
+}
+
+/// Get a segment of a rendition's HLS stream as MP3 packed audio
+#[get("/hls/{song_id}/{rendition}/{sample_number}.mp3")]
+async fn hls_segment(path: web::Path<(uuid::Uuid, String, u32)>, storage: web::Data<dyn Storage>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let (song_id, name, sample_number) = path.into_inner();
+    let (song, segments, rendition) = db::run(&pool, move |conn| {
+        let song = get_song(conn, &song_id)?;
+        let segments = get_song_segments(conn, &song_id)?;
+        let rendition = get_rendition(conn, &song_id, Some(&name))?;
+        Ok((song, segments, rendition))
+    }).await?;
+
+    // Lossless renditions are encoded as they are requested
+    let mp3_bytes = match rendition.codec {
+        Codec::Mp3 => get_sample_from_bucket(storage.get_ref(), &rendition, sample_number).await?,
+        Codec::Wav | Codec::Flac => lossless_segment_to_mp3(storage.get_ref(), &song, &segments, &rendition, sample_number).await?
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("audio/mpeg")
+        .body(packed_audio_segment(mp3_bytes, segment_start(&song, &segments, sample_number))))
+}
+
+/// Error for a client that has made too many attempts, telling it when to try again
+fn too_many_requests(seconds: u64) -> AppError {
+    let seconds = seconds.max(1);
+    AppError::TooManyRequests(format!("Too many attempts, try again in {} seconds!", seconds), seconds)
 }
 
 #[post("/signup")]
-async fn signup(user: web::Json<PostedUser>) -> impl Responder {
+async fn signup(user: web::Json<PostedUser>, client: ClientInfo, pool: web::Data<DbPool>, limiter: web::Data<RateLimiter>) -> Result<HttpResponse, AppError> {
+    if let Err(retry_after) = limiter.check(client.ip_address.as_deref().unwrap_or("unknown")) {
+        return Err(too_many_requests(retry_after.as_secs()));
+    }
     if user.username.len() < 3 {
-        let output = SessionReturn {
-            session_id: uuid::Uuid::nil(),
-            error: "Username must be at least 3 characters long!".to_string()
-        };
-        return HttpResponse::InternalServerError().json(output);
+        return Err(AppError::Validation("Username must be at least 3 characters long!".to_string()));
     }
     if user.password.len() < 8 {
-        let output = SessionReturn {
-            session_id: uuid::Uuid::nil(),
-            error: "Password must be at least 8 characters long!".to_string()
-        };
-        return HttpResponse::InternalServerError().json(output);
+        return Err(AppError::Validation("Password must be at least 8 characters long!".to_string()));
     }
-    let connection = &mut establish_connection();
-    let user = create_user(connection, &user.username, &user.password);
-    // This handles if the user already exists
-    if user.is_err() {
-        let output = SessionReturn {
-            session_id: uuid::Uuid::nil(),
-            error: user.err().unwrap().to_string()
-        };
-        return HttpResponse::InternalServerError().json(output);
-    }
-    let session = create_session(connection, &user.unwrap().id).expect("Error creating session!");
+    let user = user.into_inner();
+    let session = db::run(&pool, move |conn| {
+        let user = create_user(conn, &user.username, &user.password)?;
+        create_session(conn, &user.id, client.user_agent.as_deref(), client.ip_address.as_deref())
+    }).await?;
     let session_return: SessionReturn = SessionReturn {
-        session_id: session.id,
         error: "".to_string()
     };
-    HttpResponse::Ok().json(session_return)
+    Ok(HttpResponse::Ok()
+        .cookie(session_cookie(&session))
+        .json(session_return))
 }
 
 #[post("/login")]
-async fn login(user: web::Json<PostedUser>) -> impl Responder {
+async fn login(user: web::Json<PostedUser>, client: ClientInfo, pool: web::Data<DbPool>, limiter: web::Data<RateLimiter>, lockout: web::Data<LockoutPolicy>) -> Result<HttpResponse, AppError> {
+    if let Err(retry_after) = limiter.check(client.ip_address.as_deref().unwrap_or("unknown")) {
+        return Err(too_many_requests(retry_after.as_secs()));
+    }
     if user.username.len() < 3 || user.password.len() < 8 {
-        let output = SessionReturn {
-            session_id: uuid::Uuid::nil(),
-            error: "Username or password is incorrect!".to_string()
-        };
-        return HttpResponse::InternalServerError().json(output);
+        return Err(AppError::Unauthorized("Username or password is incorrect!".to_string()));
     }
-    let connection = &mut establish_connection();
-    let user = verify_user(connection, &user.username, &user.password);
-
-    // This handles if the user doesn't exist or the password is incorrect
-    if user.is_err(){
-        let output = SessionReturn {
-            session_id: uuid::Uuid::nil(),
-            error: user.err().unwrap().to_string()
-        };
-        return HttpResponse::Unauthorized().json(output);
+    let user = user.into_inner();
+
+    // Locked out usernames are turned away before spending time hashing their password,
+    // the same way whether or not a user has them
+    let username = user.username.clone();
+    let locked = db::run(&pool, move |conn| locked_out_for(conn, &username)).await?;
+    if let Some(seconds) = locked {
+        return Err(too_many_requests(seconds as u64));
     }
 
+    let policy = *lockout.get_ref();
+    let user_id = db::run(&pool, move |conn| {
+        let result = verify_user(conn, &user.username, &user.password);
+        // Failed logins count towards a lockout and a successful one clears them
+        let recorded = match result {
+            Ok(_) => clear_failed_logins(conn, &user.username),
+            Err(_) => record_failed_login(conn, &user.username, &policy)
+        };
+        if let Err(err) = recorded {
+            println!("Error recording login attempt: {}", err);
+        }
+        result
+    }).await?;
+
     // Creating a session
-    let session = create_session(connection, &user.unwrap()).expect("Error creating session!");
+    let session = db::run(&pool, move |conn| {
+        create_session(conn, &user_id, client.user_agent.as_deref(), client.ip_address.as_deref())
+    }).await?;
     let session_return: SessionReturn = SessionReturn {
-        session_id: session.id,
         error: "".to_string()
     };
 
-    return HttpResponse::Ok().json(session_return);
+    Ok(HttpResponse::Ok()
+        .cookie(session_cookie(&session))
+        .json(session_return))
 }
 
 #[post("/validate_session")]
-async fn validate_session(session_data: web::Json<SessionInput>) -> impl Responder {
-    let connection = &mut establish_connection();
-    let is_valid = valid_session(connection, &session_data.session_id);
+async fn validate_session(session: Option<SessionToken>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let session_id = match session {
+        Some(SessionToken(session_id)) => session_id,
+        None => return Ok(HttpResponse::Ok().body("false"))
+    };
+    let is_valid = db::run(&pool, move |conn| valid_session(conn, &session_id)).await?;
     if is_valid {
-        return HttpResponse::Ok().body("true");
+        Ok(HttpResponse::Ok().body("true"))
     }
     else {
-        return HttpResponse::Ok().body("false");
+        Ok(HttpResponse::Ok().body("false"))
     }
 }
 
 #[post("/logout")]
-async fn logout(session_data: web::Json<SessionInput>) -> impl Responder {
-    let connection = &mut establish_connection();
-    let _ = invalidate_session(connection, &session_data.session_id);
-    return HttpResponse::Ok();
-}
-
-#[get("/user/{session_id}")]
-async fn users_info(session_data: web::Path<uuid::Uuid>) -> impl Responder {
-    let session_id = session_data.into_inner();
-    let connection = &mut establish_connection();
-    let user = get_user(connection, &session_id);
-    let user = match user {
-        Ok(user) => user,
-        Err(_) => return HttpResponse::InternalServerError().body("Error getting user info")
-    };
-    let user = UserResponse {
-        id: user.id,
-        username: user.username,
-        permissions: user.permissions
-    };
-    return HttpResponse::Ok().json(user);
+async fn logout(session: Option<SessionToken>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    if let Some(SessionToken(session_id)) = session {
+        db::run(&pool, move |conn| invalidate_session(conn, &session_id)).await?;
+    }
+    Ok(HttpResponse::Ok()
+        .cookie(removal_cookie())
+        .finish())
+}
+
+/// Get the info of the logged in user
+#[get("/me")]
+async fn me(user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let mut users = db::run(&pool, move |conn| UserResponse::load(conn, vec![user.user])).await?;
+    Ok(HttpResponse::Ok().json(users.remove(0)))
+}
+
+/// List the logged in user's active sessions
+#[get("/sessions")]
+async fn list_sessions(user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let user_id = user.user.id;
+    let sessions = db::run(&pool, move |conn| get_user_sessions(conn, &user_id)).await?;
+    let sessions: Vec<SessionResponse> = sessions.into_iter().map(|session| SessionResponse {
+        current: session.id == user.session_id,
+        id: session.id,
+        created_at: session.created_at,
+        last_seen: session.last_seen,
+        expires_at: session.expires_at,
+        user_agent: session.user_agent,
+        ip_address: session.ip_address
+    }).collect();
+    Ok(HttpResponse::Ok().json(sessions))
+}
+
+/// Revoke one of the logged in user's sessions
+#[delete("/sessions/{session_id}")]
+async fn revoke_session(user: AuthenticatedUser, path: web::Path<uuid::Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let session_id = path.into_inner();
+    let user_id = user.user.id;
+    db::run(&pool, move |conn| revoke_user_session(conn, &user_id, &session_id)).await?;
+    if session_id == user.session_id {
+        return Ok(HttpResponse::Ok().cookie(removal_cookie()).finish());
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Revoke all of the logged in user's sessions except the current one
+#[delete("/sessions")]
+async fn revoke_sessions(user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    let user_id = user.user.id;
+    let session_id = user.session_id;
+    db::run(&pool, move |conn| revoke_other_sessions(conn, &user_id, &session_id)).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Change the logged in user's password, logging out their other sessions
+#[post("/change_password")]
+async fn change_password_endpoint(user: AuthenticatedUser, input: web::Json<ChangePasswordInput>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    if input.new_password.len() < 8 {
+        return Err(AppError::Validation("Password must be at least 8 characters long!".to_string()));
+    }
+    let input = input.into_inner();
+    let user_id = user.user.id;
+    let session_id = user.session_id;
+    db::run(&pool, move |conn| {
+        // The password only changes if everyone else using the old one is logged out
+        conn.transaction::<_, AppError, _>(|conn| {
+            change_password(conn, &user_id, &input.current_password, &input.new_password)?;
+            revoke_other_sessions(conn, &user_id, &session_id)
+        })
+    }).await?;
+    Ok(HttpResponse::Ok().body("Password changed"))
+}
+
+/// Send a password reset token to a user through the notifier
+#[post("/password_reset/request")]
+async fn request_password_reset(input: web::Json<PasswordResetRequest>, client: ClientInfo, pool: web::Data<DbPool>, notifier: web::Data<dyn Notifier>, limiter: web::Data<RateLimiter>) -> Result<HttpResponse, AppError> {
+    if let Err(retry_after) = limiter.check(client.ip_address.as_deref().unwrap_or("unknown")) {
+        return Err(too_many_requests(retry_after.as_secs()));
+    }
+    let username = input.into_inner().username;
+    let result = db::run(&pool, move |conn| -> Result<Option<(Users, String)>, AppError> {
+        let user = match get_user_by_username(conn, &username)? {
+            Some(user) => user,
+            None => return Ok(None)
+        };
+        let token = create_reset_token(conn, &user.id)?;
+        Ok(Some((user, token)))
+    }).await?;
+    if let Some((user, token)) = result {
+        notifier.send_password_reset(&user, &token).await?;
+    }
+    // The response is the same whether or not the user exists so usernames can't be discovered
+    Ok(HttpResponse::Ok().body("If the account exists, a password reset token has been sent"))
 }
 
+/// Set a new password using a password reset token
+#[post("/password_reset/confirm")]
+async fn confirm_password_reset(input: web::Json<PasswordResetInput>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    if input.new_password.len() < 8 {
+        return Err(AppError::Validation("Password must be at least 8 characters long!".to_string()));
+    }
+    let input = input.into_inner();
+    let reset = db::run(&pool, move |conn| reset_password(conn, &input.token, &input.new_password)).await?;
+    if !reset {
+        return Err(AppError::Validation("Invalid or expired reset token".to_string()));
+    }
+    Ok(HttpResponse::Ok().body("Password reset"))
+}
+
+/// Largest album cover accepted with an upload.
+const MAX_COVER_BYTES: usize = 10 * 1024 * 1024;
+
+/// Largest text field accepted with an upload, like the title.
+const MAX_FIELD_BYTES: usize = 4 * 1024;
+
+/// Reads the rest of a multipart field into memory, rejecting it with `too_large` once it is over `max_bytes`
+async fn read_field(field: &mut actix_multipart::Field, max_bytes: usize, too_large: &str) -> Result<Vec<u8>, AppError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let data = match chunk {
+            Ok(data) => data,
+            Err(_) => return Err(AppError::Validation("Error reading upload".to_string()))
+        };
+        if bytes.len() + data.len() > max_bytes {
+            return Err(AppError::PayloadTooLarge(too_large.to_string()));
+        }
+        bytes.extend_from_slice(&data);
+    }
+    Ok(bytes)
+}
+
+/// Add a song from an uploaded audio file and album cover
+///
+/// The upload is queued to be split into samples by a background worker, and the job is returned
+/// so its progress can be followed at `/jobs/{job_id}`. The song is listed once it's ready.
+/// Details that aren't given as fields (`title`, `artist`, `album`, `album_artist`, `track_number`, `disc_number`,
+/// `year`, `genre` and `isrc`) are read from the tags embedded in the file.
 #[post("/song")]
-async fn add_song(mut payload: Multipart) -> impl Responder {
+async fn add_song(user: AuthenticatedUser, mut payload: Multipart, storage: web::Data<dyn Storage>, pool: web::Data<DbPool>, formats: web::Data<Vec<SegmentFormat>>, ingest: web::Data<IngestConfig>) -> Result<HttpResponse, AppError> {
+    user.require(Capability::UploadSongs)?;
     let mut other_fields: HashMap<String, String> = HashMap::new();
-    let mut duration = 0;
-    let mut output_samples: Vec<Vec<u8>> = Vec::new();
+    let mut song_file = None;
     let mut album_cover: Vec<u8> = Vec::new();
 
     while let Ok(Some(mut field)) = payload.try_next().await {
@@ -192,143 +576,238 @@ async fn add_song(mut payload: Multipart) -> impl Responder {
 
         // If the field is the file, handle separately
         if field_name == "file" {
-            let content_type = format!("{}", &mut field.content_type().essence_str());
-            let mut file_bytes = Vec::new();
-            // Store the uploaded file
-            while let Some(chunk) = field.next().await {
-                let data = chunk.unwrap();
-                file_bytes.extend_from_slice(&data);
-            }
-            if content_type == "audio/mpeg" {
-                // If the file is an mp3, convert it to wav
-                let wav_bytes = mp3_to_wav(file_bytes);
-                file_bytes = match wav_bytes {
-                    Ok(wav_bytes) => wav_bytes,
-                    Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
-                };
-            }
-            
-            // Get the duration of the audio file
-            let reader = Cursor::new(file_bytes.clone());
-            let reader = match WavReader::new(reader) {
-                Ok(r) => r,
-                Err(_) => return HttpResponse::BadRequest().body("Invalid audio file"),
-            };
-            duration = (reader.duration() / reader.spec().sample_rate) as i32;
-
-            // Get the samples for the audio file
-            let samples = get_all_samples(file_bytes);
-            output_samples = match samples {
-                Ok(samples) => samples,
-                Err(_) => return HttpResponse::BadRequest().body("Unable to get samples"),
-            };
+            // Spool the uploaded file to disk, where it waits to be processed
+            song_file = Some(spool_field(&mut field, &ingest).await?);
         }
         else if field_name == "image" {
             // Store the uploaded image
-            let mut file_bytes = web::BytesMut::new();
-            while let Some(chunk) = field.next().await {
-                let data = chunk.unwrap();
-                file_bytes.extend_from_slice(&data);
-            }
-            album_cover = file_bytes.to_vec();
+            album_cover = read_field(&mut field, MAX_COVER_BYTES, "The album cover can be at most 10 MB").await?;
         }
         else {
             // Add other fields to the HashMap
-            let mut value_bytes = web::BytesMut::new();
-            while let Some(chunk) = field.next().await {
-                let data = chunk.unwrap();
-                value_bytes.extend_from_slice(&data);
-            }
+            let value_bytes = read_field(&mut field, MAX_FIELD_BYTES, "Song details can be at most 4 KB each").await?;
 
             // Interpret bytes as a UTF-8 string and store it
-            let value = String::from_utf8(value_bytes.to_vec()).unwrap_or_default();
+            let value = String::from_utf8(value_bytes).unwrap_or_default();
             other_fields.insert(field_name, value);
         }
     }
-    // Insert into the database using Diesel
-    let new_song = NewSong {
-        title: other_fields.get("title").unwrap_or(&"Unknown Title".to_string()).to_string(),
-        artist: other_fields.get("artist").unwrap_or(&"Unknown Artist".to_string()).to_string(),
-        album: other_fields.get("album").unwrap_or(&"Unknown Album".to_string()).to_string(),
-        duration,
-        num_samples: output_samples.len() as i32
+    let song_file = match song_file {
+        Some(song_file) => song_file,
+        None => return Err(AppError::Validation("No audio file uploaded".to_string()))
     };
+    let form_tags = SongTags::from_form(&other_fields)?;
 
-    let connection = &mut establish_connection();
-    let response = insert_song(connection, new_song).await;
-    let added_song = match response {
-        Ok(response) => response,
-        Err(_) => return HttpResponse::InternalServerError().body("Error adding song")
-    };
-    // Upload the album cover to the bucket
+    // Decode the album cover before adding the song so a bad image doesn't leave a song behind
     let image = ImageReader::new(Cursor::new(album_cover)).with_guessed_format();
     let image = match image {
         Ok(image) => image,
-        Err(_) => return HttpResponse::InternalServerError().body("Error reading album cover")
+        Err(_) => return Err(AppError::Validation("Error reading album cover".to_string()))
     };
     let image = image.decode();
     let image = match image {
         Ok(image) => image,
-        Err(_) => return HttpResponse::InternalServerError().body("Error decoding album cover")
+        Err(_) => return Err(AppError::Validation("Error decoding album cover".to_string()))
     };
     let mut png_data: Vec<u8> = Vec::new();
     let resp = image.write_to(&mut Cursor::new(&mut png_data), ImageFormat::Png);
-    match resp {
-        Ok(_) => {},
-        Err(_) => return HttpResponse::InternalServerError().body("Error uploading album cover")
-    };
-    let key = format!("{0}/{0}.png", added_song.id);
-    let resp = upload_file_to_bucket(&key, png_data).await;
     if resp.is_err() {
-        return HttpResponse::InternalServerError().body("Error uploading album cover");
+        return Err(AppError::Internal("Error encoding album cover".to_string()));
     }
 
-    // Upload the samples to the bucket
-    // This may take a while
-    for i in 0..output_samples.len() {
-        let key = format!("{}/{}.wav", added_song.id, i);
-        let current = output_samples.get(i).unwrap().clone();
-        let resp = upload_file_to_bucket(&key, current).await;
-        if resp.is_err() {
-            return HttpResponse::InternalServerError().body("Error uploading samples");
+    // Make sure the upload can be decoded before queueing it, so a bad file is rejected straight away,
+    // and read its tags for any details that weren't given with it
+    let probe_path = song_file.path().to_path_buf();
+    let file_tags = web::block(move || UploadDecoder::open(&probe_path).map(|_| read_tags(&probe_path))).await??;
+    let tags = form_tags.or(file_tags);
+
+    // The upload is kept until its job is done, the length of the song is filled in then
+    let upload_path = match song_file.into_temp_path().keep() {
+        Ok(upload_path) => upload_path.to_string_lossy().to_string(),
+        Err(_) => return Err(AppError::Internal("Error saving upload".to_string()))
+    };
+    let song_id = uuid::Uuid::new_v4();
+    let new_song = NewSong {
+        id: song_id,
+        title: tags.title.unwrap_or("Unknown Title".to_string()),
+        artist: match tags.artists.is_empty() {
+            true => "Unknown Artist".to_string(),
+            false => tags.artists.join(", ")
+        },
+        album: tags.album.unwrap_or("Unknown Album".to_string()),
+        duration_ms: 0,
+        num_samples: 0,
+        lossless: formats.iter().any(|format| format.codec.is_lossless()),
+        album_artist: tags.album_artist,
+        track_number: tags.track_number,
+        disc_number: tags.disc_number,
+        year: tags.year,
+        genre: tags.genre,
+        isrc: tags.isrc
+    };
+
+    let staged = stage_song(new_song, png_data, upload_path.clone(), &user.user.id, storage.get_ref(), &pool).await;
+    match staged {
+        Ok(job) => Ok(HttpResponse::Accepted().json(job)),
+        Err(err) => {
+            // Undo whatever was done so the failed upload leaves nothing behind
+            let _ = tokio::fs::remove_file(&upload_path).await;
+            let cleanup = async {
+                delete_song_objects(storage.get_ref(), &song_id, &[]).await?;
+                db::run(&pool, move |conn| delete_song_record(conn, &song_id)).await
+            };
+            if let Err(cleanup_err) = cleanup.await {
+                println!("Error cleaning up failed upload of song {}: {}", song_id, cleanup_err);
+            }
+            Err(err)
         }
     }
+}
+
+/// Adds a staged song with its album cover and queues its upload to be processed.
+/// The song isn't part of the catalog until its job is done.
+async fn stage_song(song: NewSong, cover: Vec<u8>, upload_path: String, uploader: &uuid::Uuid, storage: &dyn Storage, pool: &DbPool) -> Result<IngestJob, AppError> {
+    let song = db::run(pool, move |conn| insert_song(conn, song)).await?;
+    storage.upload_file(&cover_key(&song.id), cover).await?;
+    let (song_id, uploader) = (song.id, *uploader);
+    db::run(pool, move |conn| enqueue_job(conn, &song_id, &upload_path, &uploader)).await
+}
 
-    HttpResponse::Ok().body("File upload successful")
+/// Get the status and progress of a song's ingest job.
+/// Only the user who uploaded the song and admins can see the job.
+#[get("/jobs/{job_id}")]
+async fn job_status(user: AuthenticatedUser, path: web::Path<uuid::Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    user.require(Capability::UploadSongs)?;
+    let job_id = path.into_inner();
+    let job = db::run(&pool, move |conn| get_job(conn, &job_id)).await?;
+    // Other uploaders' jobs are treated as missing so their ids can't be probed
+    if job.created_by != Some(user.user.id) && !user.user.role.can(Capability::DeleteSongs) {
+        return Err(AppError::NotFound("Job not found".to_string()));
+    }
+    Ok(HttpResponse::Ok().json(job))
 }
 
 #[delete("/song/{song_id}")]
-async fn delete_song(path: web::Path::<uuid::Uuid>) -> impl Responder {
+async fn delete_song(user: AuthenticatedUser, path: web::Path::<uuid::Uuid>, storage: web::Data<dyn Storage>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    user.require(Capability::DeleteSongs)?;
     let song_id = path.into_inner();
-    let connection = &mut establish_connection();
-    let response = delete_song_from_server(connection, &song_id).await;
-    let response = match response {
-        Ok(response) => response,
-        Err(_) => return HttpResponse::InternalServerError().body("Error deleting song")
-    };
-    HttpResponse::Ok().body(response)
+    // The song leaves the catalog before anything is deleted
+    let (_, uploads) = db::run(&pool, move |conn| begin_song_deletion(conn, &song_id)).await?;
+    for upload in uploads {
+        let _ = tokio::fs::remove_file(upload).await;
+    }
+    // If deleting the objects fails the song is kept out of the catalog, and deleting it again picks up where this left off
+    delete_song_objects(storage.get_ref(), &song_id, &[]).await?;
+    db::run(&pool, move |conn| delete_song_record(conn, &song_id)).await?;
+    Ok(HttpResponse::Ok().body("Song deleted successfully"))
+}
+
+#[get("/admin/users")]
+async fn list_users(user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    user.require(Capability::ManageUsers)?;
+    let users = db::run(&pool, |conn| {
+        let users = get_users(conn)?;
+        UserResponse::load(conn, users)
+    }).await?;
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Grants a role to a user
+#[put("/admin/users/{user_id}/role")]
+async fn grant_role(user: AuthenticatedUser, path: web::Path<uuid::Uuid>, input: web::Json<RoleInput>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    change_role(user, path.into_inner(), input.role, pool).await
+}
+
+/// Revokes a user's role, making them a listener
+#[delete("/admin/users/{user_id}/role")]
+async fn revoke_role(user: AuthenticatedUser, path: web::Path<uuid::Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    change_role(user, path.into_inner(), Role::Listener, pool).await
+}
+
+async fn change_role(user: AuthenticatedUser, user_id: uuid::Uuid, role: Role, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    user.require(Capability::ManageUsers)?;
+    // Stops admins from accidentally locking themselves out
+    if user.user.id == user_id {
+        return Err(AppError::Validation("You cannot change your own role".to_string()));
+    }
+    let mut updated = db::run(&pool, move |conn| {
+        let updated = set_user_role(conn, &user_id, role)?;
+        UserResponse::load(conn, vec![updated])
+    }).await?;
+    Ok(HttpResponse::Ok().json(updated.remove(0)))
+}
+
+/// Clear a user's failed logins and lockout
+#[post("/admin/users/{user_id}/unlock")]
+async fn unlock_user_endpoint(user: AuthenticatedUser, path: web::Path<uuid::Uuid>, pool: web::Data<DbPool>) -> Result<HttpResponse, AppError> {
+    user.require(Capability::ManageUsers)?;
+    let user_id = path.into_inner();
+    db::run(&pool, move |conn| unlock_user(conn, &user_id)).await?;
+    Ok(HttpResponse::Ok().body("User unlocked"))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allow_any_origin() // Note: This is insecure and should not be used in production
-            .allowed_headers(vec!["Content-Type"])
-            .allow_any_method();
+    let shared_storage = storage_from_env();
+    let storage = web::Data::from(shared_storage.clone());
+    let pool = web::Data::new(establish_pool());
+    let notifier = web::Data::from(notifier_from_env());
+    let limiter = web::Data::new(RateLimiter::from_env());
+    let lockout = web::Data::new(LockoutPolicy::from_env());
+    prepare_dummy_hash();
+    let formats = web::Data::new(renditions_from_env());
+    let ingest = web::Data::new(ingest_config_from_env());
+    let origins = allowed_origins_from_env();
+    spawn_session_cleanup(pool.get_ref().clone());
+    spawn_ingest_workers(pool.get_ref().clone(), shared_storage, formats.get_ref().clone(), sample_conversion_from_env(), JobConfig::from_env());
 
+    HttpServer::new(move || {
         App::new()
-            .wrap(cors)
+            .wrap(cors(&origins))
+            // Malformed request bodies and paths get the same JSON errors as everything else
+            .app_data(web::JsonConfig::default().error_handler(|err, _| {
+                error::Error::from(AppError::Validation(err.to_string()))
+            }))
+            .app_data(web::PathConfig::default().error_handler(|err, _| {
+                error::Error::from(AppError::Validation(err.to_string()))
+            }))
+            .app_data(web::QueryConfig::default().error_handler(|err, _| {
+                error::Error::from(AppError::Validation(err.to_string()))
+            }))
+            .app_data(storage.clone())
+            .app_data(pool.clone())
+            .app_data(notifier.clone())
+            .app_data(limiter.clone())
+            .app_data(lockout.clone())
+            .app_data(formats.clone())
+            .app_data(ingest.clone())
             .service(signup)
             .service(login)
             .service(validate_session)
             .service(logout)
             .service(song_info)
             .service(songs_list)
-            .service(users_info)
+            .service(me)
+            .service(list_sessions)
+            .service(revoke_session)
+            .service(revoke_sessions)
+            .service(change_password_endpoint)
+            .service(request_password_reset)
+            .service(confirm_password_reset)
             .service(samples_compressed_endpoint)
+            .service(stream_song)
+            .service(seek_song)
+            .service(song_renditions)
+            .service(hls_master_playlist)
+            .service(hls_playlist)
+            .service(hls_segment)
             .service(add_song)
+            .service(job_status)
             .service(delete_song)
+            .service(list_users)
+            .service(grant_role)
+            .service(revoke_role)
+            .service(unlock_user_endpoint)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
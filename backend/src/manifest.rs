@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+
+use diesel::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    encoding::Codec,
+    error::AppError,
+    models::{RenditionSegment, SongSegment, Songs},
+    samples::{frames_to_ms, get_song, get_song_renditions, get_song_segments, SEGMENT_SECONDS}
+};
+
+/// A song with the manifest of its segments.
+#[derive(Serialize, Debug)]
+pub struct SongInfo {
+    #[serde(flatten)]
+    pub song: Songs,
+    /// Whether the segment times were recorded when the song was added, rather than worked out from its duration
+    pub exact_segments: bool,
+    pub segments: Vec<ManifestSegment>
+}
+
+/// When one of a song's segments starts and how it is stored in each rendition.
+#[derive(Serialize, Debug)]
+pub struct ManifestSegment {
+    pub segment_number: u32,
+    pub start_ms: u64,
+    pub duration_ms: u64,
+    pub renditions: Vec<ManifestRendition>
+}
+
+/// One of a segment's stored samples.
+#[derive(Serialize, Debug)]
+pub struct ManifestRendition {
+    pub rendition: String,
+    pub codec: Codec,
+    /// Only known once the size has been recorded, which is when the song is added or first streamed
+    pub byte_length: Option<u32>,
+    /// The SHA-256 of the sample as hex, only known for songs whose checksums were recorded when they were added
+    pub checksum: Option<String>
+}
+
+/// When a recorded segment starts and how long it is, in milliseconds.
+/// Times are rounded up at both ends like the song's duration, so the segments follow each other without gaps
+/// and the last one ends with the song.
+pub fn segment_times_ms(segment: &SongSegment, sample_rate: u32) -> (u64, u64) {
+    let start_ms = frames_to_ms(segment.start_frame as u64, sample_rate);
+    let end_ms = frames_to_ms((segment.start_frame + segment.frames as i64) as u64, sample_rate);
+    (start_ms, end_ms - start_ms)
+}
+
+/// When each of a song's segments starts and how long it is, in milliseconds.
+///
+/// Songs added before their segments were recorded have segments of [`SEGMENT_SECONDS`] except the last,
+/// whose length is worked out from the song's duration.
+pub fn song_segment_times(song: &Songs, segments: &[SongSegment]) -> Vec<(u64, u64)> {
+    match song.sample_rate {
+        Some(sample_rate) if sample_rate > 0 && !segments.is_empty() => {
+            segments.iter().map(|segment| segment_times_ms(segment, sample_rate as u32)).collect()
+        },
+        _ => {
+            let segment_ms = SEGMENT_SECONDS as u64 * 1000;
+            let duration_ms = song.duration_ms.max(0) as u64;
+            (0..song.num_samples.max(0) as u64).map(|n| {
+                let remaining_ms = duration_ms.saturating_sub(n * segment_ms);
+                (n * segment_ms, remaining_ms.clamp(1000, segment_ms))
+            }).collect()
+        }
+    }
+}
+
+/// Gets a song with the manifest of its segments: when each one starts, how long it is,
+/// and the size and checksum of its sample in every rendition.
+pub fn get_song_info(conn: &mut PgConnection, song_id: &uuid::Uuid) -> Result<SongInfo, AppError> {
+    use crate::schema::rendition_segments;
+
+    let song = get_song(conn, song_id)?;
+    let segments = get_song_segments(conn, song_id)?;
+    let renditions = get_song_renditions(conn, song_id)?;
+    let rendition_ids: Vec<uuid::Uuid> = renditions.iter().map(|rendition| rendition.id).collect();
+    let stored: HashMap<(uuid::Uuid, i32), RenditionSegment> = rendition_segments::table
+        .filter(rendition_segments::rendition_id.eq_any(&rendition_ids))
+        .select(RenditionSegment::as_select())
+        .load(conn)?
+        .into_iter()
+        .map(|segment| ((segment.rendition_id, segment.segment_number), segment))
+        .collect();
+
+    let exact_segments = matches!(song.sample_rate, Some(sample_rate) if sample_rate > 0) && !segments.is_empty();
+    let segments = song_segment_times(&song, &segments).into_iter().enumerate().map(|(n, (start_ms, duration_ms))| {
+        let renditions = renditions.iter().map(|rendition| {
+            let stored = stored.get(&(rendition.id, n as i32));
+            ManifestRendition {
+                rendition: rendition.name.clone(),
+                codec: rendition.codec,
+                byte_length: stored.map(|segment| segment.byte_length as u32),
+                checksum: stored.and_then(|segment| segment.checksum.clone())
+            }
+        }).collect();
+        ManifestSegment { segment_number: n as u32, start_ms, duration_ms, renditions }
+    }).collect();
+
+    Ok(SongInfo { song, exact_segments, segments })
+}
\ No newline at end of file
@@ -1,7 +1,7 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use serde::Serialize;
-use crate::schema::*;
+use crate::{encoding::Codec, jobs::JobStatus, roles::Role, samples::SongStatus, schema::*};
 
 #[derive(Queryable, Selectable, Debug, Serialize, Clone)]
 #[diesel(table_name = users)]
@@ -9,7 +9,7 @@ use crate::schema::*;
 pub struct Users {
     pub id: uuid::Uuid,
     pub username: String,
-    pub permissions: String,
+    pub role: Role,
     pub password_hash: String,
 }
 
@@ -17,6 +17,7 @@ pub struct Users {
 #[diesel(table_name = users)]
 pub struct NewUser<'a> {
     pub username: &'a str,
+    pub role: Role,
     pub password_hash: &'a str,
 }
 
@@ -28,6 +29,10 @@ pub struct Session {
     pub id: uuid::Uuid,
     pub user_id: uuid::Uuid,
     pub expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub last_seen: NaiveDateTime,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -35,6 +40,8 @@ pub struct Session {
 pub struct NewSession<'a> {
     pub user_id: &'a uuid::Uuid,
     pub expires_at: NaiveDateTime,
+    pub user_agent: Option<&'a str>,
+    pub ip_address: Option<&'a str>,
 }
 
 #[derive(Queryable, Selectable, Debug, Serialize, Clone)]
@@ -45,16 +52,141 @@ pub struct Songs {
     pub title: String,
     pub artist: String,
     pub album: String,
-    pub duration: i32,
-    pub num_samples: i32
+    pub num_samples: i32,
+    /// Whether the song has a lossless rendition
+    pub lossless: bool,
+    /// Songs are only part of the catalog while they're ready
+    pub status: SongStatus,
+    /// Unknown for songs added before it was recorded
+    pub sample_rate: Option<i32>,
+    /// Rounded up, so the end of the song is never cut off. Songs added before it was recorded exactly have their length in whole seconds
+    pub duration_ms: i32,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>
 }
 
 #[derive(Insertable)]
 #[diesel(table_name = songs)]
 pub struct NewSong {
+    pub id: uuid::Uuid,
     pub title: String,
     pub artist: String,
     pub album: String,
-    pub duration: i32,
-    pub num_samples: i32
+    pub duration_ms: i32,
+    pub num_samples: i32,
+    pub lossless: bool,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>
+}
+
+/// One encoding of a song's samples, stored under `{key_prefix}/{n}.{extension}`.
+#[derive(Queryable, Selectable, Debug, Serialize, Clone)]
+#[diesel(table_name = song_renditions)]
+#[diesel(belongs_to(Songs, foreign_key = song_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Rendition {
+    pub id: uuid::Uuid,
+    pub song_id: uuid::Uuid,
+    pub name: String,
+    pub codec: Codec,
+    pub bitrate_kbps: Option<i32>,
+    #[serde(skip)]
+    pub key_prefix: String
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = song_renditions)]
+pub struct NewRendition<'a> {
+    pub song_id: &'a uuid::Uuid,
+    pub name: String,
+    pub codec: Codec,
+    pub bitrate_kbps: Option<i32>,
+    pub key_prefix: String
+}
+
+/// The size and checksum of one of a rendition's stored segments.
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = rendition_segments)]
+#[diesel(belongs_to(Rendition))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RenditionSegment {
+    pub rendition_id: uuid::Uuid,
+    pub segment_number: i32,
+    pub byte_length: i32,
+    /// The SHA-256 of the segment as hex, not known for sizes measured after the song was added
+    pub checksum: Option<String>
+}
+
+/// Where one of a song's segments starts and how long it is, in frames of the song's sample rate.
+#[derive(Queryable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = song_segments)]
+#[diesel(belongs_to(Songs, foreign_key = song_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SongSegment {
+    pub song_id: uuid::Uuid,
+    pub segment_number: i32,
+    pub start_frame: i64,
+    pub frames: i32
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = password_reset_tokens)]
+#[diesel(belongs_to(Users))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PasswordResetToken {
+    pub id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = password_reset_tokens)]
+pub struct NewPasswordResetToken<'a> {
+    pub user_id: &'a uuid::Uuid,
+    pub token_hash: &'a str,
+    pub expires_at: NaiveDateTime,
+}
+
+/// A queued upload whose samples are made by a background worker.
+#[derive(Queryable, Selectable, Debug, Serialize, Clone)]
+#[diesel(table_name = ingest_jobs)]
+#[diesel(belongs_to(Songs, foreign_key = song_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct IngestJob {
+    pub id: uuid::Uuid,
+    pub song_id: uuid::Uuid,
+    pub status: JobStatus,
+    /// How much of the song has been processed, in percent
+    pub progress: i32,
+    pub attempts: i32,
+    /// Why the last attempt failed
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub upload_path: String,
+    #[serde(skip)]
+    pub run_after: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// The user who uploaded the song
+    #[serde(skip)]
+    pub created_by: Option<uuid::Uuid>
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ingest_jobs)]
+pub struct NewIngestJob<'a> {
+    pub song_id: &'a uuid::Uuid,
+    pub upload_path: &'a str,
+    pub created_by: &'a uuid::Uuid
 }
\ No newline at end of file
@@ -0,0 +1,82 @@
+use std::{
+    env,
+    path::PathBuf,
+    sync::Arc
+};
+use async_trait::async_trait;
+use dotenvy::dotenv;
+use tokio::io::AsyncWriteExt;
+
+use crate::{error::AppError, models::Users};
+
+/// Delivers messages to users outside of the website, such as password reset tokens.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Sends a password reset token to the user.
+    async fn send_password_reset(&self, user: &Users, token: &str) -> Result<(), AppError>;
+}
+
+/// Creates the notifier selected by the `NOTIFIER` environment variable.
+///
+/// `log` (the default) prints messages to the server log.
+/// `file` appends messages to the file given by `NOTIFIER_FILE` (defaults to `notifications.log`).
+pub fn notifier_from_env() -> Arc<dyn Notifier> {
+    dotenv().ok();
+
+    let notifier = env::var("NOTIFIER").unwrap_or("log".to_string());
+    match notifier.to_lowercase().as_str() {
+        "log" => Arc::new(LogNotifier),
+        "file" => {
+            let path = env::var("NOTIFIER_FILE").unwrap_or("notifications.log".to_string());
+            Arc::new(FileNotifier::new(path))
+        },
+        other => panic!("Unknown NOTIFIER: {}", other)
+    }
+}
+
+fn password_reset_message(user: &Users, token: &str) -> String {
+    format!("Password reset requested for {} ({}). Reset token: {}", user.username, user.id, token)
+}
+
+/// Prints messages to the server log, for local development.
+pub struct LogNotifier;
+
+#[async_trait]
+impl Notifier for LogNotifier {
+    async fn send_password_reset(&self, user: &Users, token: &str) -> Result<(), AppError> {
+        println!("{}", password_reset_message(user, token));
+        Ok(())
+    }
+}
+
+/// Appends messages to a file, for local development.
+pub struct FileNotifier {
+    path: PathBuf
+}
+
+impl FileNotifier {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileNotifier { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn send_password_reset(&self, user: &Users, token: &str) -> Result<(), AppError> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await;
+        let mut file = match file {
+            Ok(file) => file,
+            Err(_) => return Err(AppError::Internal("Error opening notifications file".to_string()))
+        };
+
+        let line = format!("{} {}\n", chrono::Utc::now().to_rfc3339(), password_reset_message(user, token));
+        match file.write_all(line.as_bytes()).await {
+            Ok(_) => Ok(()),
+            Err(_) => Err(AppError::Internal("Error writing to notifications file".to_string()))
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,94 @@
+use diesel::prelude::*;
+use dotenvy::dotenv;
+use password_hash::rand_core::{
+    OsRng,
+    RngCore
+};
+use sha2::{
+    Digest,
+    Sha256
+};
+
+use crate::{
+    auth::{hash_password, revoke_all_sessions},
+    config::env_or,
+    error::AppError,
+    models::*
+};
+
+/// How long a reset token can be used for, set by `PASSWORD_RESET_TOKEN_MINUTES` (defaults to 30 minutes).
+pub fn reset_token_lifetime() -> chrono::Duration {
+    dotenv().ok();
+
+    chrono::Duration::minutes(env_or("PASSWORD_RESET_TOKEN_MINUTES", 30))
+}
+
+/// Only the SHA-256 hash of a token is stored, so a leaked table can't be used to reset passwords.
+fn hash_token(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Creates a single-use reset token for a user, replacing any unused tokens they already have.
+///
+/// Returns the token to send to the user.
+pub fn create_reset_token(conn: &mut PgConnection, arg_user_id: &uuid::Uuid) -> Result<String, AppError> {
+    use crate::schema::password_reset_tokens::dsl::*;
+
+    let token = generate_token();
+    let hashed = hash_token(&token);
+    let new_token = NewPasswordResetToken {
+        user_id: arg_user_id,
+        token_hash: &hashed,
+        expires_at: chrono::Utc::now().naive_utc() + reset_token_lifetime(),
+    };
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        diesel::delete(password_reset_tokens.filter(user_id.eq(arg_user_id)).filter(used_at.is_null()))
+            .execute(conn)?;
+        diesel::insert_into(password_reset_tokens)
+            .values(&new_token)
+            .execute(conn)?;
+        Ok(())
+    })?;
+    Ok(token)
+}
+
+/// Sets a new password using a reset token and logs the user out everywhere.
+///
+/// Returns `false` if the token doesn't exist, has expired or has already been used.
+pub fn reset_password(conn: &mut PgConnection, token: &str, new_password: &str) -> Result<bool, AppError> {
+    use crate::schema::{password_reset_tokens, users};
+
+    let hashed = hash_token(token);
+    let new_hash = hash_password(new_password)?;
+
+    conn.transaction::<_, AppError, _>(|conn| {
+        let now = chrono::Utc::now().naive_utc();
+        // Marking the token as used in the same statement that checks it makes it single-use
+        let reset_user = diesel::update(password_reset_tokens::table
+                .filter(password_reset_tokens::token_hash.eq(&hashed))
+                .filter(password_reset_tokens::used_at.is_null())
+                .filter(password_reset_tokens::expires_at.gt(now)))
+            .set(password_reset_tokens::used_at.eq(now))
+            .returning(password_reset_tokens::user_id)
+            .get_result::<uuid::Uuid>(conn)
+            .optional()?;
+        let reset_user = match reset_user {
+            Some(reset_user) => reset_user,
+            None => return Ok(false)
+        };
+
+        diesel::update(users::table.filter(users::id.eq(reset_user)))
+            .set(users::password_hash.eq(&new_hash))
+            .execute(conn)?;
+        revoke_all_sessions(conn, &reset_user)?;
+        Ok(true)
+    })
+}
\ No newline at end of file
@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant}
+};
+
+use diesel::prelude::*;
+use dotenvy::dotenv;
+
+use crate::{config::env_or, error::AppError};
+
+/// Limits how many attempts each client (keyed by IP address) can make in a fixed window.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    attempts: Mutex<HashMap<String, (Instant, u32)>>
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            limit,
+            window,
+            attempts: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Allows `AUTH_RATE_LIMIT` attempts (default 10) per `AUTH_RATE_WINDOW_SECS` seconds (default 60).
+    pub fn from_env() -> Self {
+        dotenv().ok();
+
+        let limit = env_or("AUTH_RATE_LIMIT", 10);
+        let window = Duration::from_secs(env_or("AUTH_RATE_WINDOW_SECS", 60));
+        RateLimiter::new(limit, window)
+    }
+
+    /// Records an attempt from the client, returning how long it has to wait if it is over the limit.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        // Forget clients whose window has ended so the map doesn't grow forever
+        if attempts.len() > 10_000 {
+            attempts.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+        }
+
+        let entry = attempts.entry(key.to_string()).or_insert((now, 0));
+        if now.duration_since(entry.0) >= self.window {
+            *entry = (now, 0);
+        }
+        if entry.1 >= self.limit {
+            return Err(self.window - now.duration_since(entry.0));
+        }
+        entry.1 += 1;
+        Ok(())
+    }
+}
+
+/// How long logins for a username are locked out after repeated failures.
+#[derive(Clone, Copy, Debug)]
+pub struct LockoutPolicy {
+    /// Number of failed logins allowed before the username is locked out
+    pub max_attempts: i32,
+    /// How long the first lockout lasts, in seconds
+    pub base_secs: i64,
+    /// The longest a lockout can last, in seconds
+    pub max_lockout_secs: i64
+}
+
+impl LockoutPolicy {
+    /// The first `LOGIN_MAX_ATTEMPTS` failures (default 5) are free, after that the lockout starts at
+    /// `LOGIN_LOCKOUT_SECS` seconds (default 30) and doubles with each failure up to `LOGIN_MAX_LOCKOUT_SECS` (default 3600).
+    pub fn from_env() -> Self {
+        dotenv().ok();
+
+        LockoutPolicy {
+            max_attempts: env_or("LOGIN_MAX_ATTEMPTS", 5),
+            base_secs: env_or("LOGIN_LOCKOUT_SECS", 30),
+            max_lockout_secs: env_or("LOGIN_MAX_LOCKOUT_SECS", 3600)
+        }
+    }
+
+    /// How long a username is locked out after `failed_attempts` failed logins, if at all.
+    pub fn lockout_duration(&self, failed_attempts: i32) -> Option<chrono::Duration> {
+        if failed_attempts < self.max_attempts {
+            return None;
+        }
+        let doublings = (failed_attempts - self.max_attempts).min(20) as u32;
+        let seconds = self.base_secs.saturating_mul(2_i64.saturating_pow(doublings)).min(self.max_lockout_secs);
+        Some(chrono::Duration::seconds(seconds))
+    }
+}
+
+/// Returns how many seconds are left on the username's lockout, if it is locked out.
+///
+/// Usernames are locked out whether or not a user has them, so a lockout doesn't reveal which accounts exist.
+pub fn locked_out_for(conn: &mut PgConnection, arg_username: &str) -> Result<Option<i64>, AppError> {
+    use crate::schema::login_failures::dsl::*;
+
+    let response = login_failures
+        .filter(username.eq(arg_username))
+        .select(locked_until)
+        .first::<Option<chrono::NaiveDateTime>>(conn)
+        .optional()?
+        .flatten();
+
+    let remaining = response.map(|until| (until - chrono::Utc::now().naive_utc()).num_seconds());
+    match remaining {
+        Some(remaining) if remaining > 0 => Ok(Some(remaining)),
+        _ => Ok(None)
+    }
+}
+
+/// Counts a failed login against the username, locking it out once it has too many.
+pub fn record_failed_login(conn: &mut PgConnection, arg_username: &str, policy: &LockoutPolicy) -> Result<(), AppError> {
+    use crate::schema::login_failures::dsl::*;
+
+    let now = chrono::Utc::now().naive_utc();
+    let attempts = diesel::insert_into(login_failures)
+        .values((username.eq(arg_username), failed_attempts.eq(1), updated_at.eq(now)))
+        .on_conflict(username)
+        .do_update()
+        .set((failed_attempts.eq(failed_attempts + 1), updated_at.eq(now)))
+        .returning(failed_attempts)
+        .get_result::<i32>(conn)?;
+
+    if let Some(duration) = policy.lockout_duration(attempts) {
+        diesel::update(login_failures.filter(username.eq(arg_username)))
+            .set(locked_until.eq(now + duration))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Clears the failed logins and any lockout of a username.
+pub fn clear_failed_logins(conn: &mut PgConnection, arg_username: &str) -> Result<(), AppError> {
+    use crate::schema::login_failures::dsl::*;
+
+    diesel::delete(login_failures.filter(username.eq(arg_username))).execute(conn)?;
+    Ok(())
+}
+
+/// Clears the user's failed logins and any lockout, failing with [`AppError::NotFound`] if the user doesn't exist.
+pub fn unlock_user(conn: &mut PgConnection, arg_user_id: &uuid::Uuid) -> Result<(), AppError> {
+    use crate::schema::users::dsl::*;
+
+    let response = users.filter(id.eq(arg_user_id)).select(username).first::<String>(conn).optional()?;
+    match response {
+        Some(response) => clear_failed_logins(conn, &response),
+        None => Err(AppError::NotFound("User not found".to_string()))
+    }
+}
+
+/// When the lockouts of the given usernames end, for the ones that are locked out.
+pub fn get_lockouts(conn: &mut PgConnection, arg_usernames: &[String]) -> Result<HashMap<String, chrono::NaiveDateTime>, AppError> {
+    use crate::schema::login_failures::dsl::*;
+
+    let response = login_failures
+        .filter(username.eq_any(arg_usernames))
+        .filter(locked_until.gt(chrono::Utc::now().naive_utc()))
+        .select((username, locked_until.assume_not_null()))
+        .load::<(String, chrono::NaiveDateTime)>(conn)?;
+    Ok(response.into_iter().collect())
+}
+
+/// Forgets the failed logins of usernames that aren't locked out and haven't failed for `LOGIN_MAX_LOCKOUT_SECS`,
+/// so failures don't add up forever and attempted usernames don't pile up.
+pub fn purge_login_failures(conn: &mut PgConnection, policy: &LockoutPolicy) -> Result<usize, AppError> {
+    use crate::schema::login_failures::dsl::*;
+
+    let now = chrono::Utc::now().naive_utc();
+    let forgotten = updated_at.lt(now - chrono::Duration::seconds(policy.max_lockout_secs));
+    let unlocked = locked_until.is_null().or(locked_until.le(now));
+    Ok(diesel::delete(login_failures.filter(forgotten.and(unlocked))).execute(conn)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: LockoutPolicy = LockoutPolicy { max_attempts: 3, base_secs: 30, max_lockout_secs: 200 };
+
+    #[test]
+    fn the_first_failures_are_free() {
+        assert_eq!(POLICY.lockout_duration(0), None);
+        assert_eq!(POLICY.lockout_duration(2), None);
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        assert_eq!(POLICY.lockout_duration(3), Some(chrono::Duration::seconds(30)));
+        assert_eq!(POLICY.lockout_duration(4), Some(chrono::Duration::seconds(60)));
+        assert_eq!(POLICY.lockout_duration(5), Some(chrono::Duration::seconds(120)));
+        assert_eq!(POLICY.lockout_duration(6), Some(chrono::Duration::seconds(200)));
+        assert_eq!(POLICY.lockout_duration(i32::MAX), Some(chrono::Duration::seconds(200)));
+    }
+
+    #[test]
+    fn clients_over_the_limit_wait_for_the_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert_eq!(limiter.check("1.2.3.4"), Ok(()));
+        assert_eq!(limiter.check("1.2.3.4"), Ok(()));
+        let retry_after = limiter.check("1.2.3.4").unwrap_err();
+        assert!(retry_after > Duration::from_secs(59) && retry_after <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn clients_are_limited_separately() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        assert_eq!(limiter.check("1.2.3.4"), Ok(()));
+        assert!(limiter.check("1.2.3.4").is_err());
+        assert_eq!(limiter.check("5.6.7.8"), Ok(()));
+    }
+
+    #[test]
+    fn attempts_are_forgotten_when_the_window_ends() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(20));
+        assert_eq!(limiter.check("1.2.3.4"), Ok(()));
+        assert!(limiter.check("1.2.3.4").is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(limiter.check("1.2.3.4"), Ok(()));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,110 @@
+use std::collections::{HashMap, HashSet};
+
+use diesel::prelude::*;
+
+use crate::{
+    db::{self, DbPool},
+    error::AppError,
+    jobs::JobStatus,
+    models::{Rendition, Songs},
+    samples::{cover_key, delete_song_objects, delete_song_record, sample_key, SongStatus},
+    storage::Storage
+};
+
+/// What a reconciliation of the storage against the `songs` table found.
+#[derive(Debug, Default)]
+pub struct ReconcileReport {
+    /// Objects that don't belong to any song in the catalog
+    pub orphaned: Vec<String>,
+    /// Objects that songs in the catalog should have but don't
+    pub missing: Vec<String>,
+    /// Songs left staged by an upload that never queued a job or whose job failed
+    pub abandoned: Vec<uuid::Uuid>,
+    /// Songs whose deletion didn't finish
+    pub unfinished_deletions: Vec<uuid::Uuid>
+}
+
+/// The songs table and the renditions and active jobs of its songs.
+struct Catalog {
+    songs: Vec<Songs>,
+    renditions: HashMap<uuid::Uuid, Vec<Rendition>>,
+    /// Songs with a job that is queued or processing, whose objects are still being uploaded
+    processing: HashSet<uuid::Uuid>
+}
+
+fn load_catalog(conn: &mut PgConnection) -> Result<Catalog, AppError> {
+    use crate::schema::{ingest_jobs, song_renditions, songs};
+
+    let all_songs = songs::table.select(Songs::as_select()).load(conn)?;
+    let all_renditions = song_renditions::table.select(Rendition::as_select()).load(conn)?;
+    let processing = ingest_jobs::table
+        .filter(ingest_jobs::status.eq_any([JobStatus::Queued, JobStatus::Processing]))
+        .select(ingest_jobs::song_id)
+        .load::<uuid::Uuid>(conn)?;
+
+    let mut renditions: HashMap<uuid::Uuid, Vec<Rendition>> = HashMap::new();
+    for rendition in all_renditions {
+        renditions.entry(rendition.song_id).or_default().push(rendition);
+    }
+    Ok(Catalog { songs: all_songs, renditions, processing: processing.into_iter().collect() })
+}
+
+/// Scans the storage against the `songs` table, reporting objects that are orphaned or missing.
+///
+/// With `fix` set, orphaned objects are deleted and deletions that didn't finish are completed.
+/// Missing objects can't be restored, so songs that have them are only reported.
+/// Objects are listed before the catalog is read, so songs added while this runs are never mistaken for orphans,
+/// though a song that becomes ready in the meantime may be reported as missing objects.
+pub async fn reconcile(pool: &DbPool, storage: &dyn Storage, fix: bool) -> Result<ReconcileReport, AppError> {
+    let keys = storage.list_files("").await?;
+    let catalog = db::run(pool, load_catalog).await?;
+
+    let mut report = ReconcileReport::default();
+    let mut expected: HashSet<String> = HashSet::new();
+    let mut in_progress: HashSet<String> = HashSet::new();
+    for song in &catalog.songs {
+        match song.status {
+            SongStatus::Ready => {
+                expected.insert(cover_key(&song.id));
+                for rendition in catalog.renditions.get(&song.id).into_iter().flatten() {
+                    expected.extend((0..song.num_samples).map(|n| sample_key(rendition, n as u32)));
+                }
+            },
+            // Samples are still being uploaded, so anything under the song's prefix may be needed
+            SongStatus::Staged if catalog.processing.contains(&song.id) => {
+                in_progress.insert(format!("{}/", song.id));
+            },
+            SongStatus::Staged => {
+                expected.insert(cover_key(&song.id));
+                report.abandoned.push(song.id);
+            },
+            SongStatus::Deleting => report.unfinished_deletions.push(song.id)
+        }
+    }
+
+    let present: HashSet<&String> = keys.iter().collect();
+    report.missing = expected.iter().filter(|key| !present.contains(key)).cloned().collect();
+    report.missing.sort();
+    report.orphaned = keys.iter()
+        .filter(|key| !expected.contains(*key))
+        .filter(|key| !in_progress.iter().any(|prefix| key.starts_with(prefix.as_str())))
+        .cloned()
+        .collect();
+    report.orphaned.sort();
+
+    if !fix {
+        return Ok(report);
+    }
+    for key in &report.orphaned {
+        match storage.delete_file(key).await {
+            Ok(_) | Err(AppError::NotFound(_)) => {},
+            Err(err) => return Err(err)
+        }
+    }
+    for song_id in report.unfinished_deletions.clone() {
+        delete_song_objects(storage, &song_id, &[]).await?;
+        db::run(pool, move |conn| delete_song_record(conn, &song_id)).await?;
+    }
+
+    Ok(report)
+}
\ No newline at end of file
@@ -0,0 +1,83 @@
+use std::io::Write;
+
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    serialize::{self, IsNull, Output, ToSql}
+};
+use serde::{
+    Deserialize,
+    Serialize
+};
+
+use crate::schema::sql_types::UserRole;
+
+/// The role of a user, stored in the `user_role` postgres enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = UserRole)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can only listen to the catalog
+    Listener,
+    /// Can add songs to the catalog
+    Uploader,
+    /// Can do everything, including managing other users
+    Admin
+}
+
+/// Something a user is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    UploadSongs,
+    DeleteSongs,
+    ManageUsers,
+    ModeratePlaylists
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Listener => "listener",
+            Role::Uploader => "uploader",
+            Role::Admin => "admin"
+        }
+    }
+
+    /// The capabilities granted to this role.
+    pub fn capabilities(&self) -> &'static [Capability] {
+        match self {
+            Role::Listener => &[],
+            Role::Uploader => &[Capability::UploadSongs],
+            Role::Admin => &[
+                Capability::UploadSongs,
+                Capability::DeleteSongs,
+                Capability::ManageUsers,
+                Capability::ModeratePlaylists
+            ]
+        }
+    }
+
+    pub fn can(&self, capability: Capability) -> bool {
+        self.capabilities().contains(&capability)
+    }
+}
+
+impl ToSql<UserRole, Pg> for Role {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<UserRole, Pg> for Role {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"listener" => Ok(Role::Listener),
+            b"uploader" => Ok(Role::Uploader),
+            b"admin" => Ok(Role::Admin),
+            _ => Err("Unrecognized user role".into())
+        }
+    }
+}
\ No newline at end of file
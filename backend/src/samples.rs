@@ -1,179 +1,531 @@
-use std::io::Cursor;
-
-use diesel::prelude::*;
+use std::{env, io::{Cursor, Write}};
+
+use diesel::{
+    deserialize::{self, FromSql, FromSqlRow},
+    expression::AsExpression,
+    pg::{Pg, PgValue},
+    prelude::*,
+    serialize::{self, IsNull, Output, ToSql}
+};
+use dotenvy::dotenv;
 use hound::{
     WavReader, WavSpec, WavWriter
 };
-use rodio::Decoder;
-use rodio::Source;
+use serde::{
+    Deserialize,
+    Serialize
+};
+use sha2::{Digest, Sha256};
 
-use crate::{models::*, spaces::{delete_file_from_bucket, get_file_from_bucket}};
+use crate::{decoding::UploadDecoder, encoding::{Codec, SegmentFormat}, error::AppError, jobs::cancel_song_jobs, models::*, schema::sql_types, storage::Storage};
 
-pub async fn get_sample_from_bucket(song_id: &uuid::Uuid, sample_number: u32) -> Result<Vec<u8>, &'static str> {
-    let file_name = format!("{}/{}.wav", song_id, sample_number);
-    let resp = get_file_from_bucket(&file_name).await;
-    match resp {
-        Ok(resp) => return Ok(resp),
-        Err(_) => return Err("Error getting file from bucket")
-    };
+/// Length of each sample (segment) a song is split into, in seconds, unless `SEGMENT_SECONDS` is set.
+/// Songs added before the length of their samples was recorded were all split this way.
+pub const SEGMENT_SECONDS: u32 = 10;
+
+/// Longest samples a song can be split into, in seconds.
+const MAX_SEGMENT_SECONDS: u32 = 60;
+
+/// The length of a number of frames in milliseconds, rounded up so the end of the audio is never cut off.
+pub fn frames_to_ms(frames: u64, sample_rate: u32) -> u64 {
+    (frames * 1000).div_ceil(sample_rate as u64)
 }
 
-pub fn get_all_samples(file: Vec<u8>) -> Result<Vec<Vec<u8>>, &'static str> {
-    let reader = Cursor::new(file);
-    let mut reader = match WavReader::new(reader) {
-        Ok(r) => r,
-        Err(_) => return Err("Error opening audio file"),
-    };
+/// The number of samples audio of a number of frames is split into, counting the shorter last sample.
+pub fn segment_count(frames: u64, sample_rate: u32, segment_seconds: u32) -> u64 {
+    frames.div_ceil(sample_rate as u64 * segment_seconds as u64)
+}
+
+/// The key a sample of a rendition is stored under.
+pub fn sample_key(rendition: &Rendition, sample_number: u32) -> String {
+    segment_key(&rendition.key_prefix, rendition.codec, sample_number)
+}
+
+/// The key a sample is stored under, for a rendition in the given codec stored under `key_prefix`.
+pub fn segment_key(key_prefix: &str, codec: Codec, sample_number: u32) -> String {
+    format!("{}/{}.{}", key_prefix, sample_number, codec.extension())
+}
+
+/// The key prefix the samples of a song's rendition in a format are stored under.
+pub fn rendition_key_prefix(song_id: &uuid::Uuid, format: &SegmentFormat) -> String {
+    format!("{}/{}", song_id, format.name())
+}
 
-    let spec = reader.spec();
-    let sample_rate = spec.sample_rate;
-    let num_channels = spec.channels as usize;
+pub async fn get_sample_from_bucket(storage: &dyn Storage, rendition: &Rendition, sample_number: u32) -> Result<Vec<u8>, AppError> {
+    storage.get_file(&sample_key(rendition, sample_number)).await
+}
 
-    // 10 seconds per sample
-    let samples_per_segment = sample_rate * 10 * num_channels as u32;
+/// A PCM sample format that samples can be stored in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcmFormat {
+    /// Signed integer samples with the given bit depth (8, 16, 24 or 32)
+    Int(u16),
+    /// 32-bit floating point samples
+    Float
+}
 
-    // Get duration of the audio file
-    let duration = reader.duration() / sample_rate;
-    let mut num_samples = duration / 10;
-    if (duration % 10) != 0 {
-        num_samples += 1;
+impl PcmFormat {
+    /// Gets the format of the samples in a WAV file.
+    pub fn of(spec: &WavSpec) -> PcmFormat {
+        match spec.sample_format {
+            hound::SampleFormat::Int => PcmFormat::Int(spec.bits_per_sample),
+            hound::SampleFormat::Float => PcmFormat::Float
+        }
     }
 
-    let mut samples: Vec<Vec<u8>> = vec![];
+    /// The spec of a WAV file in this format.
+    pub fn spec(&self, channels: u16, sample_rate: u32) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            PcmFormat::Int(bits) => (*bits, hound::SampleFormat::Int),
+            PcmFormat::Float => (32, hound::SampleFormat::Float)
+        };
+        WavSpec { channels, sample_rate, bits_per_sample, sample_format }
+    }
+}
+
+/// How the samples of an uploaded file are converted to the format samples are stored in, and how long each sample is.
+#[derive(Clone, Copy, Debug)]
+pub struct SampleConversion {
+    /// The format samples are written in, or `None` to keep the format of the upload
+    pub target: Option<PcmFormat>,
+    /// Whether to add dither when the conversion loses precision
+    pub dither: bool,
+    /// Length of each sample in seconds, the last one can be shorter
+    pub segment_seconds: u32
+}
 
-    for _ in 0..num_samples {
-        let mut current_sample: Vec<i16> = Vec::new();
-        for sample in reader.samples::<i16>() {
-            match sample {
-                Ok(s) => current_sample.push(s),
-                Err(_) => return Err("Error reading samples")
-            };
+/// Reads how uploads are converted before they are split into samples.
+///
+/// Samples are stored in the PCM format set by `SEGMENT_FORMAT`, which every rendition is encoded from:
+/// `16`, `24` or `32` bit integers, `float` or `keep` for the upload's own format (defaults to `16`).
+/// They are dithered when they are reduced to a lower precision unless `SEGMENT_DITHER` is `false`.
+/// Songs are split into samples of `SEGMENT_SECONDS` (defaults to [`SEGMENT_SECONDS`], at most 60).
+pub fn sample_conversion_from_env() -> SampleConversion {
+    dotenv().ok();
+
+    let target = env::var("SEGMENT_FORMAT").unwrap_or("16".to_string());
+    let target = match target.to_lowercase().as_str() {
+        "16" => Some(PcmFormat::Int(16)),
+        "24" => Some(PcmFormat::Int(24)),
+        "32" => Some(PcmFormat::Int(32)),
+        "float" => Some(PcmFormat::Float),
+        "keep" => None,
+        other => panic!("Invalid SEGMENT_FORMAT: {}", other)
+    };
+    let dither = env::var("SEGMENT_DITHER").unwrap_or("true".to_string());
+    let dither = match dither.to_lowercase().as_str() {
+        "true" | "1" | "yes" => true,
+        "false" | "0" | "no" => false,
+        other => panic!("Invalid SEGMENT_DITHER: {}", other)
+    };
+    let segment_seconds = env::var("SEGMENT_SECONDS").unwrap_or(SEGMENT_SECONDS.to_string());
+    let segment_seconds = match segment_seconds.parse() {
+        Ok(seconds) if (1..=MAX_SEGMENT_SECONDS).contains(&seconds) => seconds,
+        _ => panic!("Invalid SEGMENT_SECONDS: {}", segment_seconds)
+    };
+    SampleConversion { target, dither, segment_seconds }
+}
+
+/// Triangular (TPDF) dither, from a small deterministic random number generator.
+struct Dither {
+    state: u64
+}
 
-            if current_sample.len() >= samples_per_segment as usize {
-                break;
+impl Dither {
+    fn new() -> Self {
+        Dither { state: 0x2545_f491_4f6c_dd1d }
+    }
+
+    /// Uniform noise in `[0, 1)`, from xorshift64.
+    fn uniform(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Noise between -1 and 1 least significant bits.
+    fn tpdf(&mut self) -> f64 {
+        self.uniform() - self.uniform()
+    }
+}
+
+/// Converts a sample scaled to full scale (`-1.0..1.0`) to the target format.
+fn write_converted<W: std::io::Write + std::io::Seek>(writer: &mut WavWriter<W>, sample: f64, target: PcmFormat, dither: Option<&mut Dither>) -> Result<(), hound::Error> {
+    match target {
+        PcmFormat::Float => writer.write_sample(sample as f32),
+        PcmFormat::Int(bits) => {
+            let scale = (1i64 << (bits - 1)) as f64;
+            let mut scaled = sample * scale;
+            if let Some(dither) = dither {
+                scaled += dither.tpdf();
             }
+            let value = scaled.round().clamp(-scale, scale - 1.0);
+            writer.write_sample(value as i32)
         }
-        // Write the first segment to a new WAV file in memory
+    }
+}
+
+/// Splits decoded audio into samples of the conversion's length, converted as given,
+/// passing each one on with its number of frames as soon as it has been written so only one is held in memory at a time.
+///
+/// Returns the number of frames in the audio.
+pub fn split_samples(decoder: &mut UploadDecoder, conversion: SampleConversion, mut on_sample: impl FnMut(Vec<u8>, u32) -> Result<(), AppError>) -> Result<u64, AppError> {
+    let num_channels = decoder.channels() as usize;
+    let source = decoder.format();
+    let target = conversion.target.unwrap_or(source);
+    let target_spec = target.spec(decoder.channels(), decoder.sample_rate());
+    // Dither is only needed when the target can't hold every value of the source
+    let loses_precision = match (source, target) {
+        (PcmFormat::Int(source_bits), PcmFormat::Int(target_bits)) => source_bits > target_bits,
+        (PcmFormat::Float, PcmFormat::Int(_)) => true,
+        (_, PcmFormat::Float) => false
+    };
+    let mut dither = match conversion.dither && loses_precision {
+        true => Some(Dither::new()),
+        false => None
+    };
+
+    let samples_per_segment = (decoder.sample_rate() * conversion.segment_seconds) as usize * num_channels;
+    let mut block: Vec<f64> = Vec::new();
+    let mut offset = 0;
+    let mut total = 0;
+    let mut finished = false;
+
+    while !finished {
+        // Write each segment to a new WAV file in memory
         let mut buffer = Cursor::new(Vec::new());
-        {
-            let mut writer = WavWriter::new(&mut buffer, spec).unwrap();
-            for sample in &current_sample {
-                writer.write_sample(*sample).unwrap();
+        let mut writer = match WavWriter::new(&mut buffer, target_spec) {
+            Ok(writer) => writer,
+            Err(_) => return Err(AppError::Internal("Error writing sample".to_string()))
+        };
+        let mut written = 0;
+        while written < samples_per_segment {
+            if offset == block.len() {
+                if !decoder.next_block(&mut block)? {
+                    finished = true;
+                    break;
+                }
+                offset = 0;
+            }
+            let count = (block.len() - offset).min(samples_per_segment - written);
+            for sample in &block[offset..offset + count] {
+                if write_converted(&mut writer, *sample, target, dither.as_mut()).is_err() {
+                    return Err(AppError::Internal("Error writing sample".to_string()));
+                }
             }
-            writer.finalize().unwrap();
+            offset += count;
+            written += count;
+        }
+        if writer.finalize().is_err() {
+            return Err(AppError::Internal("Error writing sample".to_string()));
         }
 
-        let audio_bytes = buffer.into_inner();
-        samples.push(audio_bytes);
+        if written > 0 {
+            total += written;
+            on_sample(buffer.into_inner(), (written / num_channels) as u32)?;
+        }
     }
 
-    Ok(samples)
+    Ok((total / num_channels) as u64)
+}
+
+/// Gets the length of a WAV file in seconds.
+pub fn wav_duration(file: Vec<u8>) -> Result<f64, AppError> {
+    let reader = match WavReader::new(Cursor::new(file)) {
+        Ok(reader) => reader,
+        Err(_) => return Err(AppError::Internal("Error opening sample".to_string()))
+    };
+    Ok(reader.duration() as f64 / reader.spec().sample_rate as f64)
 }
 
-pub async fn get_songs_list(conn: &mut PgConnection) -> Result<Vec<Songs>, &'static str> {
+pub fn write_wav<S: hound::Sample + Copy>(buffer: &mut Cursor<Vec<u8>>, spec: WavSpec, samples: &[S]) -> Result<(), hound::Error> {
+    let mut writer = WavWriter::new(buffer, spec)?;
+    for sample in samples {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()
+}
+
+/// Where a song is in its life, stored in the `song_status` postgres enum.
+///
+/// A song's objects are only uploaded while it's staged and only deleted once it's marked as deleting,
+/// so the catalog never has a song with missing samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = sql_types::SongStatus)]
+#[serde(rename_all = "lowercase")]
+pub enum SongStatus {
+    /// The song has been added but its samples are still being made
+    Staged,
+    /// Every object of the song is in the storage, and it's part of the catalog
+    Ready,
+    /// The song's objects are being deleted
+    Deleting
+}
+
+impl SongStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SongStatus::Staged => "staged",
+            SongStatus::Ready => "ready",
+            SongStatus::Deleting => "deleting"
+        }
+    }
+}
+
+impl ToSql<sql_types::SongStatus, Pg> for SongStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<sql_types::SongStatus, Pg> for SongStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"staged" => Ok(SongStatus::Staged),
+            b"ready" => Ok(SongStatus::Ready),
+            b"deleting" => Ok(SongStatus::Deleting),
+            _ => Err("Unrecognized song status".into())
+        }
+    }
+}
+
+/// The key a song's album cover is stored under.
+pub fn cover_key(song_id: &uuid::Uuid) -> String {
+    format!("{0}/{0}.png", song_id)
+}
+
+/// Lists the songs that are ready to be played.
+pub fn get_songs_list(conn: &mut PgConnection) -> Result<Vec<Songs>, AppError> {
     use crate::schema::songs::dsl::*;
 
-    let response = songs.select(Songs::as_select()).load(conn);
-    let response = match response {
-        Ok(response) => response,
-        Err(_) => return Err("Error loading songs")
-    };
+    let response = songs.filter(status.eq(SongStatus::Ready)).select(Songs::as_select()).load(conn)?;
 
     Ok(response)
 }
 
-pub async fn get_song(conn: &mut PgConnection, song_id: &uuid::Uuid) -> Result<Songs, &'static str> {
+/// Gets a song from the catalog, songs that aren't ready are treated as not found.
+pub fn get_song(conn: &mut PgConnection, song_id: &uuid::Uuid) -> Result<Songs, AppError> {
     use crate::schema::songs::dsl::*;
 
-    let response = songs.filter(id.eq(song_id)).select(Songs::as_select()).load(conn);
-    let response = match response {
-        Ok(response) => response,
-        Err(_) => return Err("Error loading songs")
-    };
-    let response = response.get(0);
-    let response = match response {
-        Some(response) => response,
-        None => return Err("Error loading song")
-    };
-
-    return Ok(response.clone());
+    let response = songs
+        .filter(id.eq(song_id))
+        .filter(status.eq(SongStatus::Ready))
+        .select(Songs::as_select())
+        .first(conn)
+        .optional()?;
+    match response {
+        Some(response) => Ok(response),
+        None => Err(AppError::NotFound("Song not found".to_string()))
+    }
 }
 
-pub async fn insert_song(conn: &mut PgConnection, song: NewSong) -> Result<Songs, &'static str> {
+/// Adds a staged song, which isn't part of the catalog until its samples and renditions have been made.
+pub fn insert_song(conn: &mut PgConnection, song: NewSong) -> Result<Songs, AppError> {
     use crate::schema::songs;
 
     let result = diesel::insert_into(songs::table)
         .values(&song)
         .returning(Songs::as_returning())
-        .get_result(conn);
-    let result = match result {
-        Ok(result) => result,
-        Err(_) => return Err("Error adding song")
-    };
+        .get_result(conn)?;
 
-    return Ok(result);
+    Ok(result)
 }
 
-pub async fn delete_song_from_server(conn: &mut PgConnection, song_id: &uuid::Uuid) -> Result<&'static str, &'static str> {
-    use crate::schema::songs::dsl::*;
+/// Records the renditions a song's samples have been stored in.
+pub fn insert_renditions(conn: &mut PgConnection, song_id: &uuid::Uuid, formats: &[SegmentFormat]) -> Result<Vec<Rendition>, AppError> {
+    use crate::schema::song_renditions;
+
+    let new_renditions: Vec<NewRendition> = formats.iter().map(|format| NewRendition {
+        song_id,
+        name: format.name(),
+        codec: format.codec,
+        bitrate_kbps: format.recorded_bitrate(),
+        key_prefix: rendition_key_prefix(song_id, format)
+    }).collect();
+    let renditions = diesel::insert_into(song_renditions::table)
+        .values(&new_renditions)
+        .returning(Rendition::as_returning())
+        .get_results(conn)?;
+
+    Ok(renditions)
+}
 
-    let response = songs.filter(id.eq(song_id)).select(Songs::as_select()).load(conn);
-    let response = match response {
-        Ok(response) => response,
-        Err(_) => return Err("Error loading songs")
+/// Gets the renditions of a song, from the lowest bitrate to lossless.
+/// Songs that aren't ready have none.
+pub fn get_song_renditions(conn: &mut PgConnection, arg_song_id: &uuid::Uuid) -> Result<Vec<Rendition>, AppError> {
+    use crate::schema::{song_renditions::dsl::*, songs};
+
+    let response = song_renditions
+        .inner_join(songs::table)
+        .filter(song_id.eq(arg_song_id))
+        .filter(songs::status.eq(SongStatus::Ready))
+        .order(bitrate_kbps.asc().nulls_last())
+        .select(Rendition::as_select())
+        .load(conn)?;
+    Ok(response)
+}
+
+/// Gets a rendition of a song by name, or the song's default rendition if no name is given.
+///
+/// The default is the highest bitrate lossy rendition, so clients that don't choose get a reasonable
+/// stream, falling back to lossless for songs that have no lossy renditions.
+pub fn get_rendition(conn: &mut PgConnection, song_id: &uuid::Uuid, name: Option<&str>) -> Result<Rendition, AppError> {
+    let renditions = get_song_renditions(conn, song_id)?;
+    let rendition = match name {
+        Some(name) => renditions.into_iter().find(|rendition| rendition.name == name),
+        None => default_rendition(renditions)
     };
-    let response = response.get(0);
-    let response = match response {
-        Some(response) => response,
-        None => return Err("Error loading song")
+    match rendition {
+        Some(rendition) => Ok(rendition),
+        None => Err(AppError::NotFound("Rendition not found".to_string()))
+    }
+}
+
+/// Gets the rendition in the first of the given codecs that a song has been encoded in,
+/// picking the highest bitrate when there are several.
+///
+/// When none of the codecs match the song's default rendition is used, unless `strict` is set.
+pub fn negotiate_rendition(conn: &mut PgConnection, song_id: &uuid::Uuid, codecs: &[Codec], strict: bool) -> Result<Rendition, AppError> {
+    let renditions = get_song_renditions(conn, song_id)?;
+    choose_rendition(renditions, codecs, strict)
+}
+
+/// Picks a rendition in the first of the given codecs out of some of a song's renditions, like [`negotiate_rendition`].
+pub fn choose_rendition(renditions: Vec<Rendition>, codecs: &[Codec], strict: bool) -> Result<Rendition, AppError> {
+    let negotiated = codecs.iter().find_map(|codec| {
+        renditions.iter().filter(|rendition| rendition.codec == *codec).max_by_key(|rendition| rendition.bitrate_kbps).cloned()
+    });
+    let rendition = match (negotiated, strict) {
+        (Some(rendition), _) => Some(rendition),
+        (None, true) => None,
+        (None, false) => default_rendition(renditions)
     };
-    let sample_num = response.num_samples;
-
-    diesel::delete(songs.filter(id.eq(song_id))).execute(conn).expect("Error deleting song");
-    for i in 0..sample_num {
-        let response = delete_file_from_bucket(format!("{}/{}.wav", song_id, i)).await;
-        match response {
-            Ok(_) => true,
-            Err(_) => return Err("Error deleting samples")
-        };
+    match rendition {
+        Some(rendition) => Ok(rendition),
+        None => Err(AppError::NotFound("Song is not available in the requested format".to_string()))
     }
+}
 
-    Ok("Song deleted successfully")
+/// The rendition sent when a client doesn't ask for one: the best lossy rendition, as lossless ones are much larger.
+fn default_rendition(renditions: Vec<Rendition>) -> Option<Rendition> {
+    let lossy = renditions.iter().filter(|rendition| !rendition.codec.is_lossless()).max_by_key(|rendition| rendition.bitrate_kbps).cloned();
+    lossy.or(renditions.into_iter().next())
 }
 
-pub fn mp3_to_wav(mp3_bytes: Vec<u8>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // Create a Cursor for in-memory MP3 bytes
-    let mp3_cursor = Cursor::new(mp3_bytes);
+/// The SHA-256 checksum of a stored sample, as hex.
+pub fn sample_checksum(sample: &[u8]) -> String {
+    let digest = Sha256::digest(sample);
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
 
-    // Decode MP3 bytes into a Rodio Source
-    let source = Decoder::new(mp3_cursor);
-    let source = match source {
-        Ok(source) => source,
-        Err(_) => return Err("Error decoding MP3 file".into())
-    };
+/// Records the size of each of a rendition's samples, and the checksums of as many of them as are given.
+/// Sizes that were already recorded are kept.
+pub fn insert_segment_sizes(conn: &mut PgConnection, rendition_id: &uuid::Uuid, sizes: &[u32], checksums: &[String]) -> Result<(), AppError> {
+    use crate::schema::rendition_segments;
+
+    let segments: Vec<RenditionSegment> = sizes.iter().enumerate().map(|(n, size)| RenditionSegment {
+        rendition_id: *rendition_id,
+        segment_number: n as i32,
+        byte_length: *size as i32,
+        checksum: checksums.get(n).cloned()
+    }).collect();
+    diesel::insert_into(rendition_segments::table)
+        .values(&segments)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Records where each of a song's samples starts from the number of frames in each.
+pub fn insert_song_segments(conn: &mut PgConnection, song_id: &uuid::Uuid, segment_frames: &[u32]) -> Result<(), AppError> {
+    use crate::schema::song_segments;
+
+    let mut start_frame = 0;
+    let segments: Vec<SongSegment> = segment_frames.iter().enumerate().map(|(n, frames)| {
+        let segment = SongSegment { song_id: *song_id, segment_number: n as i32, start_frame, frames: *frames as i32 };
+        start_frame += *frames as i64;
+        segment
+    }).collect();
+    diesel::insert_into(song_segments::table)
+        .values(&segments)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    Ok(())
+}
 
-    // Create an in-memory buffer for WAV bytes
-    let mut wav_buffer = Cursor::new(Vec::new());
+/// Gets where each of a song's samples starts, in order, if they were recorded when it was added.
+pub fn get_song_segments(conn: &mut PgConnection, arg_song_id: &uuid::Uuid) -> Result<Vec<SongSegment>, AppError> {
+    use crate::schema::song_segments::dsl::*;
 
-    // Set up WAV writer with appropriate specifications
-    let spec = WavSpec {
-        channels: source.channels() as u16,
-        sample_rate: source.sample_rate(),
-        bits_per_sample: 16,
-        sample_format: hound::SampleFormat::Int
-    };
+    let response = song_segments
+        .filter(song_id.eq(arg_song_id))
+        .order(segment_number.asc())
+        .select(SongSegment::as_select())
+        .load(conn)?;
+    Ok(response)
+}
 
-    let mut wav_writer = WavWriter::new(&mut wav_buffer, spec)?;
+/// Gets the sizes of a rendition's samples that have been recorded, in order.
+pub fn get_segment_sizes(conn: &mut PgConnection, arg_rendition_id: &uuid::Uuid) -> Result<Vec<RenditionSegment>, AppError> {
+    use crate::schema::rendition_segments::dsl::*;
 
-    // Write decoded samples into the WAV writer
-    for sample in source.convert_samples::<i16>() {
-        wav_writer.write_sample(sample)?;
-    }
+    let response = rendition_segments
+        .filter(rendition_id.eq(arg_rendition_id))
+        .order(segment_number.asc())
+        .select(RenditionSegment::as_select())
+        .load(conn)?;
+    Ok(response)
+}
 
-    // Finalize the WAV file
-    wav_writer.finalize()?;
+/// Marks a song as deleting, taking it out of the catalog before any of its objects are deleted.
+/// Any of its uploads still waiting to be processed are cancelled, and their spooled files returned so they can be removed.
+///
+/// Songs in any state can be deleted, including ones whose deletion failed part way through.
+pub fn begin_song_deletion(conn: &mut PgConnection, song_id: &uuid::Uuid) -> Result<(Songs, Vec<String>), AppError> {
+    use crate::schema::songs::dsl::*;
+
+    conn.transaction(|conn| {
+        let response = diesel::update(songs.filter(id.eq(song_id)))
+            .set(status.eq(SongStatus::Deleting))
+            .returning(Songs::as_returning())
+            .get_result(conn)
+            .optional()?;
+        let response = match response {
+            Some(response) => response,
+            None => return Err(AppError::NotFound("Song not found".to_string()))
+        };
+        let uploads = cancel_song_jobs(conn, song_id)?;
+
+        Ok((response, uploads))
+    })
+}
+
+/// Removes a song from the database once its objects have been deleted.
+/// Its renditions and jobs are deleted along with it.
+pub fn delete_song_record(conn: &mut PgConnection, song_id: &uuid::Uuid) -> Result<(), AppError> {
+    use crate::schema::songs::dsl::*;
+
+    diesel::delete(songs.filter(id.eq(song_id))).execute(conn)?;
+
+    Ok(())
+}
+
+/// Deletes every object stored under a song's key prefix, returning how many were deleted.
+/// Objects that are already gone are skipped, so a deletion that failed part way through can be tried again.
+pub async fn delete_song_objects(storage: &dyn Storage, song_id: &uuid::Uuid, keep: &[String]) -> Result<usize, AppError> {
+    let keys = storage.list_files(&format!("{}/", song_id)).await?;
+    let mut deleted = 0;
+    for key in keys.iter().filter(|key| !keep.contains(key)) {
+        match storage.delete_file(key).await {
+            Ok(_) => deleted += 1,
+            Err(AppError::NotFound(_)) => {},
+            Err(err) => return Err(err)
+        }
+    }
 
-    // Retrieve the WAV data as a vector of bytes
-    let wav_bytes = wav_buffer.into_inner();
-    Ok(wav_bytes)
+    Ok(deleted)
 }
\ No newline at end of file
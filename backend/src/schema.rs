@@ -1,39 +1,159 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "job_status"))]
+    pub struct JobStatus;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "segment_codec"))]
+    pub struct SegmentCodec;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "song_status"))]
+    pub struct SongStatus;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "user_role"))]
+    pub struct UserRole;
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JobStatus;
+
+    ingest_jobs (id) {
+        id -> Uuid,
+        song_id -> Uuid,
+        status -> JobStatus,
+        progress -> Int4,
+        attempts -> Int4,
+        error -> Nullable<Varchar>,
+        upload_path -> Varchar,
+        run_after -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        created_by -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    login_failures (username) {
+        username -> Varchar,
+        failed_attempts -> Int4,
+        locked_until -> Nullable<Timestamp>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    password_reset_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    rendition_segments (rendition_id, segment_number) {
+        rendition_id -> Uuid,
+        segment_number -> Int4,
+        byte_length -> Int4,
+        checksum -> Nullable<Varchar>,
+    }
+}
+
 diesel::table! {
     session (id) {
         id -> Uuid,
         user_id -> Uuid,
         expires_at -> Timestamp,
+        created_at -> Timestamp,
+        last_seen -> Timestamp,
+        user_agent -> Nullable<Varchar>,
+        ip_address -> Nullable<Varchar>,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::SegmentCodec;
+
+    song_renditions (id) {
+        id -> Uuid,
+        song_id -> Uuid,
+        name -> Varchar,
+        codec -> SegmentCodec,
+        bitrate_kbps -> Nullable<Int4>,
+        key_prefix -> Varchar,
+    }
+}
+
+diesel::table! {
+    song_segments (song_id, segment_number) {
+        song_id -> Uuid,
+        segment_number -> Int4,
+        start_frame -> Int8,
+        frames -> Int4,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::SongStatus;
+
     songs (id) {
         id -> Uuid,
         title -> Varchar,
         artist -> Varchar,
         album -> Varchar,
-        duration -> Int4,
         num_samples -> Int4,
+        lossless -> Bool,
+        status -> SongStatus,
+        sample_rate -> Nullable<Int4>,
+        duration_ms -> Int4,
+        album_artist -> Nullable<Varchar>,
+        track_number -> Nullable<Int4>,
+        disc_number -> Nullable<Int4>,
+        year -> Nullable<Int4>,
+        genre -> Nullable<Varchar>,
+        isrc -> Nullable<Varchar>,
     }
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::UserRole;
+
     users (id) {
         id -> Uuid,
         username -> Varchar,
-        permissions -> Varchar,
+        role -> UserRole,
         password_hash -> Varchar,
         song_id -> Nullable<Uuid>,
     }
 }
 
+diesel::joinable!(ingest_jobs -> songs (song_id));
+diesel::joinable!(ingest_jobs -> users (created_by));
+diesel::joinable!(password_reset_tokens -> users (user_id));
+diesel::joinable!(rendition_segments -> song_renditions (rendition_id));
 diesel::joinable!(session -> users (user_id));
+diesel::joinable!(song_renditions -> songs (song_id));
+diesel::joinable!(song_segments -> songs (song_id));
 diesel::joinable!(users -> songs (song_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    ingest_jobs,
+    login_failures,
+    password_reset_tokens,
+    rendition_segments,
     session,
+    song_renditions,
+    song_segments,
     songs,
     users,
 );
@@ -0,0 +1,175 @@
+use serde::Serialize;
+
+use crate::{
+    error::AppError,
+    manifest::segment_times_ms,
+    models::{SongSegment, Songs},
+    samples::SEGMENT_SECONDS
+};
+
+/// Where a position in a song falls among its segments.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SeekPosition {
+    pub segment_number: u32,
+    /// When the segment starts in the song, in milliseconds
+    pub segment_start_ms: u64,
+    pub segment_duration_ms: u64,
+    /// How far into the segment the position is, in milliseconds
+    pub offset_ms: u64,
+    /// How far into the segment the position is in frames, for players that can start part way through a segment exactly.
+    /// Only known for songs whose segments were recorded when they were added
+    pub offset_frames: Option<u64>
+}
+
+/// Finds the segment a position in a song is in, and how far into it the position is.
+///
+/// Songs added before their segments were recorded are assumed to have segments of [`SEGMENT_SECONDS`],
+/// which is only accurate to the millisecond.
+pub fn seek(song: &Songs, segments: &[SongSegment], position_ms: u64) -> Result<SeekPosition, AppError> {
+    let sample_rate = match song.sample_rate {
+        Some(sample_rate) if sample_rate > 0 && !segments.is_empty() => sample_rate as u64,
+        _ => return seek_fixed_length(song, position_ms)
+    };
+
+    // Positions so large they overflow are far past the end of any song
+    let position_frame = match position_ms.checked_mul(sample_rate) {
+        Some(position) => position / 1000,
+        None => return Err(past_the_end())
+    };
+    let index = segments.partition_point(|segment| (segment.start_frame + segment.frames as i64) as u64 <= position_frame);
+    let segment = match segments.get(index) {
+        Some(segment) => segment,
+        None => return Err(past_the_end())
+    };
+
+    let (segment_start_ms, segment_duration_ms) = segment_times_ms(segment, sample_rate as u32);
+    Ok(SeekPosition {
+        segment_number: segment.segment_number as u32,
+        segment_start_ms,
+        segment_duration_ms,
+        offset_ms: position_ms - segment_start_ms,
+        offset_frames: Some(position_frame - segment.start_frame as u64)
+    })
+}
+
+/// Finds a position in a song whose segments are all [`SEGMENT_SECONDS`] long except the last.
+fn seek_fixed_length(song: &Songs, position_ms: u64) -> Result<SeekPosition, AppError> {
+    let segment_ms = SEGMENT_SECONDS as u64 * 1000;
+    let segment_number = position_ms / segment_ms;
+    // The duration of these songs is in whole seconds, so the song may be up to a second longer than it says
+    let duration_ms = song.duration_ms.max(0) as u64;
+    if segment_number >= song.num_samples.max(0) as u64 || position_ms >= duration_ms + 1000 {
+        return Err(past_the_end());
+    }
+
+    let segment_start_ms = segment_number * segment_ms;
+    let remaining_ms = duration_ms.saturating_sub(segment_start_ms);
+    Ok(SeekPosition {
+        segment_number: segment_number as u32,
+        segment_start_ms,
+        segment_duration_ms: remaining_ms.clamp(1000, segment_ms),
+        offset_ms: position_ms - segment_start_ms,
+        offset_frames: None
+    })
+}
+
+fn past_the_end() -> AppError {
+    AppError::Validation("The position is past the end of the song".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::samples::SongStatus;
+
+    const SAMPLE_RATE: i64 = 44100;
+
+    fn song(sample_rate: Option<i32>, duration_ms: i32, num_samples: i32) -> Songs {
+        Songs {
+            id: uuid::Uuid::nil(),
+            title: "Song".to_string(),
+            artist: "Artist".to_string(),
+            album: "Album".to_string(),
+            num_samples,
+            lossless: false,
+            status: SongStatus::Ready,
+            sample_rate,
+            duration_ms,
+            album_artist: None,
+            track_number: None,
+            disc_number: None,
+            year: None,
+            genre: None,
+            isrc: None
+        }
+    }
+
+    /// Two full segments of 10 seconds and one of a single frame.
+    fn recorded() -> (Songs, Vec<SongSegment>) {
+        let frames = [SAMPLE_RATE * 10, SAMPLE_RATE * 10, 1];
+        let mut start_frame = 0;
+        let segments = frames.iter().enumerate().map(|(n, frames)| {
+            let segment = SongSegment { song_id: uuid::Uuid::nil(), segment_number: n as i32, start_frame, frames: *frames as i32 };
+            start_frame += frames;
+            segment
+        }).collect();
+        (song(Some(SAMPLE_RATE as i32), 20_001, 3), segments)
+    }
+
+    #[test]
+    fn positions_on_a_boundary_start_the_next_segment() {
+        let (song, segments) = recorded();
+        let before = seek(&song, &segments, 9_999).unwrap();
+        assert_eq!((before.segment_number, before.offset_ms), (0, 9_999));
+        assert_eq!(before.offset_frames, Some(SAMPLE_RATE as u64 * 9_999 / 1000));
+
+        let on = seek(&song, &segments, 10_000).unwrap();
+        assert_eq!(on, SeekPosition {
+            segment_number: 1,
+            segment_start_ms: 10_000,
+            segment_duration_ms: 10_000,
+            offset_ms: 0,
+            offset_frames: Some(0)
+        });
+    }
+
+    #[test]
+    fn the_last_frame_can_be_sought() {
+        let (song, segments) = recorded();
+        let last = seek(&song, &segments, 20_000).unwrap();
+        assert_eq!(last, SeekPosition {
+            segment_number: 2,
+            segment_start_ms: 20_000,
+            segment_duration_ms: 1,
+            offset_ms: 0,
+            offset_frames: Some(0)
+        });
+    }
+
+    #[test]
+    fn positions_past_the_end_are_rejected() {
+        let (song, segments) = recorded();
+        assert!(matches!(seek(&song, &segments, 20_001), Err(AppError::Validation(_))));
+        assert!(matches!(seek(&song, &segments, u64::MAX), Err(AppError::Validation(_))));
+        assert!(matches!(seek(&song, &segments, u64::MAX / SAMPLE_RATE as u64 + 1), Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn songs_without_recorded_segments_use_fixed_lengths() {
+        // Added before segments were recorded, 25 seconds long in three segments
+        let song = song(None, 25_000, 3);
+        let position = seek(&song, &[], 12_345).unwrap();
+        assert_eq!(position, SeekPosition {
+            segment_number: 1,
+            segment_start_ms: 10_000,
+            segment_duration_ms: 10_000,
+            offset_ms: 2_345,
+            offset_frames: None
+        });
+
+        let last = seek(&song, &[], 25_500).unwrap();
+        assert_eq!((last.segment_number, last.segment_duration_ms, last.offset_ms), (2, 5_000, 5_500));
+        assert!(matches!(seek(&song, &[], 26_000), Err(AppError::Validation(_))));
+        assert!(matches!(seek(&song, &[], u64::MAX), Err(AppError::Validation(_))));
+    }
+}
\ No newline at end of file
@@ -1,123 +1,173 @@
+use async_trait::async_trait;
 use aws_config::{
     BehaviorVersion,
     Region
 };
 use aws_sdk_s3::{
     config::{
+        retry::RetryConfig,
+        timeout::TimeoutConfig,
         Builder,
         Credentials
     }, primitives::ByteStream, Client
 };
-use std::env;
+use std::{
+    env,
+    time::Duration
+};
 use dotenvy::dotenv;
 
-/// Gets a file from a bucket from its file name.
-pub async fn get_file_from_bucket(file_name: &str) -> Result<Vec<u8>, &'static str> {
-    dotenv().ok();
-
-    // Environment variables
-    let access_key = env::var("DO_ACCESS_KEY_ID").expect("DO_ACCESS_KEY_ID must be set");
-    let secret_key = env::var("DO_SECRET_ACCESS_KEY").expect("DO_SECRET_ACCESS_KEY must be set");
-    let region = env::var("DO_REGION").expect("DO_REGION must be set");
-    let endpoint = env::var("DO_ENDPOINT").expect("DO_ENDPOINT must be set");
-    let bucket_name = env::var("DO_BUCKET_NAME").expect("DO_BUCKET_NAME must be set");
-
-    let region = Region::new(region);
-
-    let credentials = Credentials::new(access_key, secret_key, None, None, "Digital Ocean");
-    
-    let config = Builder::new()
-        .region(region)
-        .credentials_provider(credentials)
-        .behavior_version(BehaviorVersion::latest())
-        .endpoint_url(endpoint)
-        .build();
-
-    let client = Client::from_conf(config);
-
-    let resp = client.get_object().bucket(bucket_name).key(file_name).send().await;
-    let resp = match resp {
-        Ok(resp) => resp,
-        Err(_) => return Err("Failed to get object from bucket!")
-    };
-
-    let output = resp.body.collect().await;
-    let output = match output {
-        Ok(output) => output.into_bytes().to_vec(),
-        Err(_) => return Err("Failed to collect body from response")
-    };
-
-    Ok(output)
+use crate::{config::env_or, error::AppError, storage::Storage};
+
+/// Stores files in a Digital Ocean Spaces (S3 compatible) bucket.
+///
+/// The client is built once and shared between requests so that its
+/// connection pool is reused for every object.
+pub struct SpacesStorage {
+    client: Client,
+    bucket_name: String
 }
 
-pub async fn upload_file_to_bucket(file_name: &str, file_bytes: Vec<u8>) -> Result<&'static str, &'static str> {
-    dotenv().ok();
-
-    // Environment variables
-    let access_key = env::var("DO_ACCESS_KEY_ID").expect("DO_ACCESS_KEY_ID must be set");
-    let secret_key = env::var("DO_SECRET_ACCESS_KEY").expect("DO_SECRET_ACCESS_KEY must be set");
-    let region = env::var("DO_REGION").expect("DO_REGION must be set");
-    let endpoint = env::var("DO_ENDPOINT").expect("DO_ENDPOINT must be set");
-    let bucket_name = env::var("DO_BUCKET_NAME").expect("DO_BUCKET_NAME must be set");
-
-    let region = Region::new(region);
-
-    let credentials = Credentials::new(access_key, secret_key, None, None, "Digital Ocean");
-    
-    let config = Builder::new()
-        .region(region)
-        .credentials_provider(credentials)
-        .behavior_version(BehaviorVersion::latest())
-        .endpoint_url(endpoint)
-        .build();
-
-    let client = Client::from_conf(config);
-
-    let body = ByteStream::from(file_bytes);
-    let response = client
-        .put_object()
-        .bucket(bucket_name)
-        .key(file_name)
-        .body(body)
-        .send()
-        .await;
-    match response {
-        Ok(response) => response,
-        Err(err) => {
-            println!("Error uploading file to bucket: {:?}", err);
-            return Err("Error uploading file to bucket");
+impl SpacesStorage {
+    /// Builds the bucket client from the `DO_*` environment variables.
+    ///
+    /// Timeouts and retries can be tuned with `DO_CONNECT_TIMEOUT_SECS` (default 5),
+    /// `DO_OPERATION_TIMEOUT_SECS` (default 30) and `DO_MAX_ATTEMPTS` (default 3).
+    pub fn from_env() -> Self {
+        dotenv().ok();
+
+        // Environment variables
+        let access_key = env::var("DO_ACCESS_KEY_ID").expect("DO_ACCESS_KEY_ID must be set");
+        let secret_key = env::var("DO_SECRET_ACCESS_KEY").expect("DO_SECRET_ACCESS_KEY must be set");
+        let region = env::var("DO_REGION").expect("DO_REGION must be set");
+        let endpoint = env::var("DO_ENDPOINT").expect("DO_ENDPOINT must be set");
+        let bucket_name = env::var("DO_BUCKET_NAME").expect("DO_BUCKET_NAME must be set");
+
+        let connect_timeout = env_or("DO_CONNECT_TIMEOUT_SECS", 5);
+        let operation_timeout = env_or("DO_OPERATION_TIMEOUT_SECS", 30);
+        let max_attempts = env_or("DO_MAX_ATTEMPTS", 3);
+
+        let region = Region::new(region);
+
+        let credentials = Credentials::new(access_key, secret_key, None, None, "Digital Ocean");
+
+        let timeout_config = TimeoutConfig::builder()
+            .connect_timeout(Duration::from_secs(connect_timeout))
+            .operation_timeout(Duration::from_secs(operation_timeout))
+            .build();
+
+        let config = Builder::new()
+            .region(region)
+            .credentials_provider(credentials)
+            .behavior_version(BehaviorVersion::latest())
+            .endpoint_url(endpoint)
+            .timeout_config(timeout_config)
+            .retry_config(RetryConfig::standard().with_max_attempts(max_attempts))
+            .build();
+
+        SpacesStorage {
+            client: Client::from_conf(config),
+            bucket_name
         }
-    };
-    Ok("File uploaded successfully")
+    }
 }
 
-pub async fn delete_file_from_bucket(file_name: String) -> Result<&'static str, &'static str> {
-    dotenv().ok();
-
-    // Environment variables
-    let access_key = env::var("DO_ACCESS_KEY_ID").expect("DO_ACCESS_KEY_ID must be set");
-    let secret_key = env::var("DO_SECRET_ACCESS_KEY").expect("DO_SECRET_ACCESS_KEY must be set");
-    let region = env::var("DO_REGION").expect("DO_REGION must be set");
-    let endpoint = env::var("DO_ENDPOINT").expect("DO_ENDPOINT must be set");
-    let bucket_name = env::var("DO_BUCKET_NAME").expect("DO_BUCKET_NAME must be set");
-
-    let region = Region::new(region);
-
-    let credentials = Credentials::new(access_key, secret_key, None, None, "Digital Ocean");
-    
-    let config = Builder::new()
-        .region(region)
-        .credentials_provider(credentials)
-        .behavior_version(BehaviorVersion::latest())
-        .endpoint_url(endpoint)
-        .build();
-
-    let client = Client::from_conf(config);
-
-    let resp = client.delete_object().bucket(bucket_name).key(file_name).send().await;
-    match resp {
-        Ok(resp) => resp,
-        Err(_) => return Err("Failed to delete object from bucket!")
-    };
-    Ok("File deleted successfully")
+#[async_trait]
+impl Storage for SpacesStorage {
+    /// Gets a file from a bucket from its file name.
+    async fn get_file(&self, file_name: &str) -> Result<Vec<u8>, AppError> {
+        let resp = self.client.get_object().bucket(&self.bucket_name).key(file_name).send().await;
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(err) => {
+                if err.as_service_error().map(|err| err.is_no_such_key()).unwrap_or(false) {
+                    return Err(AppError::NotFound("File not found".to_string()));
+                }
+                println!("Error getting object from bucket: {:?}", err);
+                return Err(AppError::Storage("Failed to get object from bucket!".to_string()));
+            }
+        };
+
+        let output = resp.body.collect().await;
+        let output = match output {
+            Ok(output) => output.into_bytes().to_vec(),
+            Err(_) => return Err(AppError::Storage("Failed to collect body from response".to_string()))
+        };
+
+        Ok(output)
+    }
+
+    async fn upload_file(&self, file_name: &str, file_bytes: Vec<u8>) -> Result<&'static str, AppError> {
+        let body = ByteStream::from(file_bytes);
+        let response = self.client
+            .put_object()
+            .bucket(&self.bucket_name)
+            .key(file_name)
+            .body(body)
+            .send()
+            .await;
+        match response {
+            Ok(response) => response,
+            Err(err) => {
+                println!("Error uploading file to bucket: {:?}", err);
+                return Err(AppError::Storage("Error uploading file to bucket".to_string()));
+            }
+        };
+        Ok("File uploaded successfully")
+    }
+
+    async fn delete_file(&self, file_name: &str) -> Result<&'static str, AppError> {
+        let resp = self.client.delete_object().bucket(&self.bucket_name).key(file_name).send().await;
+        match resp {
+            Ok(resp) => resp,
+            Err(err) => {
+                println!("Error deleting object from bucket: {:?}", err);
+                return Err(AppError::Storage("Failed to delete object from bucket!".to_string()));
+            }
+        };
+        Ok("File deleted successfully")
+    }
+
+    async fn list_files(&self, prefix: &str) -> Result<Vec<String>, AppError> {
+        let mut keys = Vec::new();
+        let mut continuation_token = None;
+        // Objects are listed a page at a time
+        loop {
+            let resp = self.client
+                .list_objects_v2()
+                .bucket(&self.bucket_name)
+                .prefix(prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await;
+            let resp = match resp {
+                Ok(resp) => resp,
+                Err(err) => {
+                    println!("Error listing objects in bucket: {:?}", err);
+                    return Err(AppError::Storage("Failed to list objects in bucket!".to_string()));
+                }
+            };
+            keys.extend(resp.contents().iter().filter_map(|object| object.key().map(str::to_string)));
+            continuation_token = match resp.next_continuation_token() {
+                Some(token) => Some(token.to_string()),
+                None => break
+            };
+        }
+        Ok(keys)
+    }
+
+    async fn file_size(&self, file_name: &str) -> Result<u64, AppError> {
+        let resp = self.client.head_object().bucket(&self.bucket_name).key(file_name).send().await;
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(err) => {
+                if err.as_service_error().map(|err| err.is_not_found()).unwrap_or(false) {
+                    return Err(AppError::NotFound("File not found".to_string()));
+                }
+                println!("Error getting object metadata from bucket: {:?}", err);
+                return Err(AppError::Storage("Failed to get object from bucket!".to_string()));
+            }
+        };
+        Ok(resp.content_length().unwrap_or(0).max(0) as u64)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,174 @@
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+    sync::Arc
+};
+use async_trait::async_trait;
+use dotenvy::dotenv;
+
+use crate::{error::AppError, spaces::SpacesStorage};
+
+/// A place where song objects (samples and album covers) are kept.
+///
+/// Keys are relative paths such as `{song_id}/{n}.wav` and `{song_id}/{song_id}.png`,
+/// and must resolve to the same object on every backend.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Gets a file from the storage from its key.
+    async fn get_file(&self, key: &str) -> Result<Vec<u8>, AppError>;
+
+    /// Uploads a file to the storage under the given key, replacing any existing file.
+    async fn upload_file(&self, key: &str, file_bytes: Vec<u8>) -> Result<&'static str, AppError>;
+
+    /// Deletes the file stored under the given key.
+    async fn delete_file(&self, key: &str) -> Result<&'static str, AppError>;
+
+    /// Lists the keys of every file whose key starts with the given prefix, such as `{song_id}/`.
+    async fn list_files(&self, prefix: &str) -> Result<Vec<String>, AppError>;
+
+    /// Gets the size in bytes of the file stored under the given key, without fetching it.
+    async fn file_size(&self, key: &str) -> Result<u64, AppError>;
+}
+
+/// Creates the storage backend selected by the `STORAGE_BACKEND` environment variable.
+///
+/// `spaces` (the default) uses the S3 compatible bucket configured by the `DO_*` variables.
+/// `local` stores files in the directory given by `LOCAL_STORAGE_DIR` (defaults to `samples`).
+pub fn storage_from_env() -> Arc<dyn Storage> {
+    dotenv().ok();
+
+    let backend = env::var("STORAGE_BACKEND").unwrap_or("spaces".to_string());
+    match backend.to_lowercase().as_str() {
+        "local" => {
+            let directory = env::var("LOCAL_STORAGE_DIR").unwrap_or("samples".to_string());
+            Arc::new(LocalStorage::new(directory))
+        },
+        "spaces" | "s3" => Arc::new(SpacesStorage::from_env()),
+        other => panic!("Unknown STORAGE_BACKEND: {}", other)
+    }
+}
+
+/// Stores files in a directory on the local filesystem.
+pub struct LocalStorage {
+    root: PathBuf
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+
+    /// Resolves a key to a path inside the storage directory.
+    /// Keys that would escape the directory are rejected.
+    fn path_for(&self, key: &str) -> Result<PathBuf, AppError> {
+        let relative = Path::new(key);
+        let is_safe = relative.components().all(|component| matches!(component, Component::Normal(_)));
+        if !is_safe || key.is_empty() {
+            return Err(AppError::Validation("Invalid file key".to_string()));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn get_file(&self, key: &str) -> Result<Vec<u8>, AppError> {
+        let path = self.path_for(key)?;
+        match tokio::fs::read(path).await {
+            Ok(bytes) => Ok(bytes),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(AppError::NotFound("File not found".to_string())),
+            Err(_) => Err(AppError::Storage("Failed to read file from local storage!".to_string()))
+        }
+    }
+
+    async fn upload_file(&self, key: &str, file_bytes: Vec<u8>) -> Result<&'static str, AppError> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            if tokio::fs::create_dir_all(parent).await.is_err() {
+                return Err(AppError::Storage("Error creating local storage directory".to_string()));
+            }
+        }
+        match tokio::fs::write(path, file_bytes).await {
+            Ok(_) => Ok("File uploaded successfully"),
+            Err(err) => {
+                println!("Error writing file to local storage: {:?}", err);
+                Err(AppError::Storage("Error uploading file to local storage".to_string()))
+            }
+        }
+    }
+
+    async fn delete_file(&self, key: &str) -> Result<&'static str, AppError> {
+        let path = self.path_for(key)?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(_) => {
+                // Directories left empty are removed too, so deleted songs leave nothing behind
+                let mut parent = path.parent();
+                while let Some(directory) = parent {
+                    if directory == self.root || tokio::fs::remove_dir(directory).await.is_err() {
+                        break;
+                    }
+                    parent = directory.parent();
+                }
+                Ok("File deleted successfully")
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(AppError::NotFound("File not found".to_string())),
+            Err(_) => Err(AppError::Storage("Failed to delete file from local storage!".to_string()))
+        }
+    }
+
+    async fn list_files(&self, prefix: &str) -> Result<Vec<String>, AppError> {
+        // Only the directory the prefix is in has to be searched
+        let directory = match prefix.rfind('/') {
+            Some(end) => self.path_for(&prefix[..end])?,
+            None => self.root.clone()
+        };
+
+        let mut keys = Vec::new();
+        let mut directories = vec![directory];
+        while let Some(directory) = directories.pop() {
+            let mut entries = match tokio::fs::read_dir(&directory).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(_) => return Err(AppError::Storage("Failed to list files in local storage!".to_string()))
+            };
+            loop {
+                let entry = match entries.next_entry().await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(_) => return Err(AppError::Storage("Failed to list files in local storage!".to_string()))
+                };
+                let path = entry.path();
+                let is_dir = match entry.file_type().await {
+                    Ok(file_type) => file_type.is_dir(),
+                    Err(_) => return Err(AppError::Storage("Failed to list files in local storage!".to_string()))
+                };
+                if is_dir {
+                    directories.push(path);
+                    continue;
+                }
+                // Keys always use forward slashes, whatever the platform
+                let key = match path.strip_prefix(&self.root) {
+                    Ok(relative) => relative.components()
+                        .map(|component| component.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    Err(_) => continue
+                };
+                if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn file_size(&self, key: &str) -> Result<u64, AppError> {
+        let path = self.path_for(key)?;
+        match tokio::fs::metadata(path).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(AppError::NotFound("File not found".to_string())),
+            Err(_) => Err(AppError::Storage("Failed to read file from local storage!".to_string()))
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,303 @@
+use std::{ops::Range, sync::Arc};
+
+use actix_web::web::Bytes;
+use futures::{stream, Stream, StreamExt};
+
+use crate::{
+    encoding::{mp3_frame_header, Codec},
+    error::AppError,
+    models::Rendition,
+    samples::{get_sample_from_bucket, sample_key},
+    storage::Storage
+};
+
+/// Number of segments fetched ahead of the one being sent.
+const SEGMENTS_IN_FLIGHT: usize = 2;
+
+/// Whether a rendition's segments can be joined into one file that players can read from start to end.
+///
+/// MP3 frames can simply be put one after another, and WAV segments can once their headers are replaced
+/// by one for the whole song. FLAC streams can't be joined, so FLAC renditions are left out.
+pub fn can_stream(codec: Codec) -> bool {
+    matches!(codec, Codec::Mp3 | Codec::Wav)
+}
+
+/// Gets the size of each of a rendition's samples from the storage, for songs added before sizes were recorded.
+pub async fn measure_segment_sizes(storage: &dyn Storage, rendition: &Rendition, num_samples: u32) -> Result<Vec<u32>, AppError> {
+    let mut sizes = Vec::with_capacity(num_samples as usize);
+    for n in 0..num_samples {
+        sizes.push(storage.file_size(&sample_key(rendition, n)).await? as u32);
+    }
+    Ok(sizes)
+}
+
+/// A rendition of a song laid out as one file: a header followed by part of each of its segments.
+pub struct SongStream {
+    /// Sent before the first segment, the WAV header of the whole song for WAV renditions
+    header: Vec<u8>,
+    /// The key of each segment and the range of its bytes that are part of the stream
+    segments: Vec<(String, Range<u64>)>,
+    size: u64
+}
+
+impl SongStream {
+    /// Lays out a rendition from the sizes of its segments.
+    ///
+    /// Every segment starts the same way, so the first one is fetched to find what has to be left out of the others.
+    /// WAV segments each have their own header, which is replaced by a header for the whole song.
+    /// MP3 segments are cut from one stream, but those of songs added before they were each start with a LAME tag frame,
+    /// which is left out as players would take the first one as the length of the whole song and play the others as gaps.
+    pub async fn new(storage: &dyn Storage, rendition: &Rendition, sizes: &[u32]) -> Result<Self, AppError> {
+        let (header, skip) = match rendition.codec {
+            Codec::Flac => return Err(AppError::Validation("FLAC renditions can't be streamed as one file".to_string())),
+            _ if sizes.is_empty() => (Vec::new(), 0),
+            Codec::Wav => {
+                let first = get_sample_from_bucket(storage, rendition, 0).await?;
+                let data_start = match wav_data_start(&first) {
+                    Some(data_start) => data_start,
+                    None => return Err(AppError::Internal("Error reading sample".to_string()))
+                };
+                let data_size: u64 = sizes.iter().map(|size| (*size as u64).saturating_sub(data_start as u64)).sum();
+                (wav_header(&first[..data_start], data_size), data_start as u64)
+            },
+            Codec::Mp3 => {
+                let first = get_sample_from_bucket(storage, rendition, 0).await?;
+                (Vec::new(), mp3_tag_frame_len(&first) as u64)
+            }
+        };
+
+        let segments: Vec<(String, Range<u64>)> = sizes.iter().enumerate().map(|(n, size)| {
+            let size = *size as u64;
+            (sample_key(rendition, n as u32), skip.min(size)..size)
+        }).collect();
+        let size = header.len() as u64 + segments.iter().map(|(_, range)| range.end - range.start).sum::<u64>();
+
+        Ok(SongStream { header, segments, size })
+    }
+
+    /// The size of the whole file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Streams the bytes from `start` to `end` (inclusive) of the file, fetching only the segments they are in.
+    pub fn bytes(self, storage: Arc<dyn Storage>, start: u64, end: u64) -> impl Stream<Item = Result<Bytes, AppError>> + 'static {
+        let (header, parts) = self.parts(start, end);
+        let header = match header.is_empty() {
+            true => None,
+            false => Some(Ok(Bytes::copy_from_slice(&self.header[header])))
+        };
+
+        let segments = stream::iter(parts)
+            .map(move |(key, range)| {
+                let storage = storage.clone();
+                async move {
+                    let sample = storage.get_file(&key).await?;
+                    // The sample has to be the size that was recorded or the stream would be corrupted
+                    if range.end > sample.len() {
+                        return Err(AppError::Storage("Sample is smaller than expected".to_string()));
+                    }
+                    Ok(Bytes::from(sample).slice(range))
+                }
+            })
+            .buffered(SEGMENTS_IN_FLIGHT);
+        stream::iter(header).chain(segments)
+    }
+
+    /// Finds the bytes from `start` to `end` (inclusive) of the file: the part of the header,
+    /// and the key of each segment they are in with the part of it that is needed.
+    fn parts(&self, start: u64, end: u64) -> (Range<usize>, Vec<(String, Range<usize>)>) {
+        let end = end + 1;
+        let header_len = self.header.len() as u64;
+        let header = start.min(header_len) as usize..end.min(header_len).max(start.min(header_len)) as usize;
+
+        // Each segment's place in the file, and the part of it inside the requested range
+        let mut offset = header_len;
+        let mut parts = Vec::new();
+        for (key, range) in &self.segments {
+            let length = range.end - range.start;
+            let (from, to) = (start.max(offset), end.min(offset + length));
+            if from < to {
+                let skip = range.start + from - offset;
+                parts.push((key.clone(), skip as usize..(skip + to - from) as usize));
+            }
+            offset += length;
+        }
+        (header, parts)
+    }
+}
+
+/// Finds where the samples of a WAV file start, after its `data` chunk header.
+fn wav_data_start(wav: &[u8]) -> Option<usize> {
+    if wav.len() < 12 || &wav[..4] != b"RIFF" || &wav[8..12] != b"WAVE" {
+        return None;
+    }
+    let mut position = 12;
+    while position + 8 <= wav.len() {
+        let id = &wav[position..position + 4];
+        let size = u32::from_le_bytes(wav[position + 4..position + 8].try_into().ok()?) as usize;
+        if id == b"data" {
+            return Some(position + 8);
+        }
+        // Chunks are padded to an even number of bytes
+        position += 8 + size + size % 2;
+    }
+    None
+}
+
+/// Gets the length of the Xing or LAME tag frame at the start of an MP3 file, or 0 if it doesn't start with one.
+fn mp3_tag_frame_len(mp3: &[u8]) -> usize {
+    let header = match mp3_frame_header(mp3) {
+        Some(header) => header,
+        None => return 0
+    };
+    // The tag comes right after the side information, whose size depends on the version and channels
+    let tag_at = 4 + match (header.mpeg1, header.mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9
+    };
+    match mp3.get(tag_at..tag_at + 4) {
+        Some(b"Xing") | Some(b"Info") if header.len <= mp3.len() => header.len,
+        _ => 0
+    }
+}
+
+/// Copies the header of a WAV segment, setting the sizes in it to those of a file with `data_size` bytes of samples.
+fn wav_header(segment_header: &[u8], data_size: u64) -> Vec<u8> {
+    let mut header = segment_header.to_vec();
+    let data_size = data_size.min((u32::MAX - header.len() as u32) as u64) as u32;
+    let riff_size = data_size + header.len() as u32 - 8;
+    header[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    let data_size_at = header.len() - 4;
+    header[data_size_at..].copy_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use actix_web::http::header::ByteRangeSpec;
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    /// A 4 byte header followed by bytes 2 to 5 of segment `a` and 2 to 7 of segment `b`.
+    fn song_stream() -> SongStream {
+        SongStream {
+            header: b"HEAD".to_vec(),
+            segments: vec![("a".to_string(), 2..6), ("b".to_string(), 2..8)],
+            size: 14
+        }
+    }
+
+    fn part(key: &str, range: Range<usize>) -> (String, Range<usize>) {
+        (key.to_string(), range)
+    }
+
+    #[test]
+    fn the_first_byte_is_the_header() {
+        assert_eq!(song_stream().parts(0, 0), (0..1, vec![]));
+    }
+
+    #[test]
+    fn the_last_byte_is_the_end_of_the_last_segment() {
+        let (header, parts) = song_stream().parts(13, 13);
+        assert!(header.is_empty());
+        assert_eq!(parts, [part("b", 7..8)]);
+    }
+
+    #[test]
+    fn ranges_across_segments_take_part_of_each() {
+        assert_eq!(song_stream().parts(3, 9), (3..4, vec![part("a", 2..6), part("b", 2..4)]));
+        assert_eq!(song_stream().parts(0, 13), (0..4, vec![part("a", 2..6), part("b", 2..8)]));
+
+        let (header, parts) = song_stream().parts(5, 6);
+        assert!(header.is_empty());
+        assert_eq!(parts, [part("a", 3..5)]);
+    }
+
+    #[test]
+    fn suffix_ranges_take_the_end_of_the_last_segment() {
+        let (start, end) = ByteRangeSpec::Last(3).to_satisfiable_range(14).unwrap();
+        let (header, parts) = song_stream().parts(start, end);
+        assert!(header.is_empty());
+        assert_eq!(parts, [part("b", 5..8)]);
+    }
+
+    /// An MP3 frame with the given header, and a tag where it would be with this many bytes of side information.
+    fn mp3_frame(header: [u8; 4], len: usize, side_info: usize, tag: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; len];
+        frame[..4].copy_from_slice(&header);
+        frame[4 + side_info..4 + side_info + tag.len()].copy_from_slice(tag);
+        frame
+    }
+
+    #[test]
+    fn tag_frames_are_found_in_every_mpeg_version() {
+        // MPEG 1 at 128 kbps and 44.1 kHz, stereo and mono
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xFB, 0x90, 0x00], 417, 32, b"Info")), 417);
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xFB, 0x90, 0xC0], 417, 17, b"Xing")), 417);
+        // MPEG 2 at 64 kbps and 22.05 kHz
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xF3, 0x80, 0x00], 208, 17, b"Info")), 208);
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xF3, 0x80, 0xC0], 208, 9, b"Info")), 208);
+        // MPEG 2.5 at 32 kbps and 11.025 kHz
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xE3, 0x40, 0x00], 208, 17, b"Xing")), 208);
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xE3, 0x40, 0xC0], 208, 9, b"Xing")), 208);
+    }
+
+    #[test]
+    fn audio_frames_are_not_tag_frames() {
+        // The tag has to be right after the side information
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xFB, 0x90, 0x00], 417, 17, b"Info")), 0);
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xFB, 0x90, 0x00], 417, 32, b"LAME")), 0);
+        // Cut short, or with the reserved MPEG version
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xFB, 0x90, 0x00], 417, 32, b"Info")[..400]), 0);
+        assert_eq!(mp3_tag_frame_len(&mp3_frame([0xFF, 0xEB, 0x90, 0x00], 417, 32, b"Info")), 0);
+        assert_eq!(mp3_tag_frame_len(b"RIFF"), 0);
+    }
+
+    fn wav_segment(samples: &[i16]) -> Vec<u8> {
+        let spec = WavSpec { channels: 2, sample_rate: 44100, bits_per_sample: 16, sample_format: SampleFormat::Int };
+        let mut buffer = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut buffer, spec).unwrap();
+        for sample in samples {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn wav_headers_are_replaced_with_one_for_the_whole_song() {
+        let segment = wav_segment(&[1, 2, 3, 4]);
+        let data_start = wav_data_start(&segment).unwrap();
+        assert_eq!(&segment[data_start - 8..data_start - 4], b"data");
+
+        // Two segments of 4 samples, one after the other
+        let mut song = wav_header(&segment[..data_start], 16);
+        assert_eq!(song.len(), data_start);
+        song.extend_from_slice(&segment[data_start..]);
+        song.extend_from_slice(&segment[data_start..]);
+        assert_eq!(u32::from_le_bytes(song[4..8].try_into().unwrap()) as usize, song.len() - 8);
+
+        let mut reader = hound::WavReader::new(Cursor::new(song)).unwrap();
+        assert_eq!(reader.duration(), 4);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples, [1, 2, 3, 4, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn wav_chunks_before_the_data_are_skipped() {
+        let segment = wav_segment(&[1, 2]);
+        // A chunk with an odd size is followed by a padding byte
+        let mut with_list = segment[..36].to_vec();
+        with_list.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00");
+        with_list.extend_from_slice(&segment[36..]);
+        assert_eq!(wav_data_start(&with_list), Some(wav_data_start(&segment).unwrap() + 12));
+
+        assert_eq!(wav_data_start(b"RIFF\x00\x00\x00\x00WAVEfmt "), None);
+        assert_eq!(wav_data_start(&segment[..36]), None);
+    }
+}
\ No newline at end of file
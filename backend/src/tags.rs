@@ -0,0 +1,270 @@
+use std::{collections::HashMap, fs::File, path::Path};
+
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, StandardTagKey, Tag},
+    probe::Hint
+};
+
+use crate::error::AppError;
+
+/// Details of a song, from the tags embedded in its upload or the fields uploaded with it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SongTags {
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    pub isrc: Option<String>
+}
+
+impl SongTags {
+    /// Reads the details given in the fields of an upload form. Empty fields are left out.
+    pub fn from_form(fields: &HashMap<String, String>) -> Result<Self, AppError> {
+        let text = |name: &str| fields.get(name).map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+        let number = |name: &str| match text(name) {
+            Some(value) => match value.parse::<i32>() {
+                Ok(number) if number >= 0 => Ok(Some(number)),
+                _ => Err(AppError::Validation(format!("Invalid {}: {}", name.replace('_', " "), value)))
+            },
+            None => Ok(None)
+        };
+
+        Ok(SongTags {
+            title: text("title"),
+            artists: text("artist").into_iter().collect(),
+            album: text("album"),
+            album_artist: text("album_artist"),
+            track_number: number("track_number")?,
+            disc_number: number("disc_number")?,
+            year: number("year")?,
+            genre: text("genre"),
+            isrc: text("isrc")
+        })
+    }
+
+    /// Fills in the details these tags don't have from others.
+    pub fn or(self, other: SongTags) -> SongTags {
+        SongTags {
+            title: self.title.or(other.title),
+            artists: match self.artists.is_empty() {
+                true => other.artists,
+                false => self.artists
+            },
+            album: self.album.or(other.album),
+            album_artist: self.album_artist.or(other.album_artist),
+            track_number: self.track_number.or(other.track_number),
+            disc_number: self.disc_number.or(other.disc_number),
+            year: self.year.or(other.year),
+            genre: self.genre.or(other.genre),
+            isrc: self.isrc.or(other.isrc)
+        }
+    }
+
+    /// Adds the details in a list of tags that haven't already been found.
+    fn add(&mut self, tags: &[Tag]) {
+        for tag in tags {
+            let value = tag.value.to_string();
+            // Values may be padded with null characters as well as spaces
+            let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+            if value.is_empty() {
+                continue;
+            }
+            match tag.std_key {
+                Some(StandardTagKey::TrackTitle) => first(&mut self.title, value),
+                Some(StandardTagKey::Artist) => {
+                    // ID3v2.4 separates multiple values with null characters
+                    for artist in value.split('\0').map(str::trim).filter(|artist| !artist.is_empty()) {
+                        if !self.artists.iter().any(|known| known == artist) {
+                            self.artists.push(artist.to_string());
+                        }
+                    }
+                },
+                Some(StandardTagKey::Album) => first(&mut self.album, value),
+                Some(StandardTagKey::AlbumArtist) => first(&mut self.album_artist, value),
+                Some(StandardTagKey::TrackNumber) => self.track_number = self.track_number.or(leading_number(value)),
+                Some(StandardTagKey::DiscNumber) => self.disc_number = self.disc_number.or(leading_number(value)),
+                // Dates can be a full date or just the year
+                Some(StandardTagKey::Date | StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate) => {
+                    self.year = self.year.or(value.get(..4).and_then(|year| year.parse().ok()))
+                },
+                Some(StandardTagKey::Genre) => first(&mut self.genre, value),
+                Some(StandardTagKey::IdentIsrc) => first(&mut self.isrc, &value.to_uppercase()),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Keeps the first value found for a detail.
+fn first(field: &mut Option<String>, value: &str) {
+    if field.is_none() {
+        *field = Some(value.split('\0').next().unwrap_or(value).trim().to_string());
+    }
+}
+
+/// Reads numbers like track numbers, which may be given with the total as in `3/12`.
+fn leading_number(value: &str) -> Option<i32> {
+    value.split('/').next()?.trim().parse().ok().filter(|number: &i32| *number >= 0)
+}
+
+/// Reads the tags embedded in an upload: ID3v2 tags, Vorbis comments in FLAC and Ogg files,
+/// MP4 metadata atoms and WAV `INFO` chunks that come before the audio.
+///
+/// Where a file has more than one set of tags, such as an ID3v2 tag before a FLAC stream, the first one read wins.
+/// A file whose tags can't be read is treated as having none, as they are never needed to add a song.
+pub fn read_tags(path: &Path) -> SongTags {
+    let mut tags = SongTags::default();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return tags
+    };
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let probed = symphonia::default::get_probe().format(&Hint::new(), source, &FormatOptions::default(), &MetadataOptions::default());
+    let mut probed = match probed {
+        Ok(probed) => probed,
+        Err(_) => return tags
+    };
+
+    // Tags in front of the container, like ID3v2, and then the container's own
+    if let Some(mut metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.skip_to_latest() {
+            tags.add(revision.tags());
+        }
+    }
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        tags.add(revision.tags());
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A second of silent 16-bit mono WAV with an `INFO` chunk holding the given tags.
+    fn tagged_wav(tags: &[(&[u8; 4], &str)]) -> tempfile::NamedTempFile {
+        let mut info = b"INFO".to_vec();
+        for (id, value) in tags {
+            // Values are null terminated and chunks are padded to an even length
+            let mut value = value.as_bytes().to_vec();
+            value.push(0);
+            info.extend_from_slice(*id);
+            info.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            if value.len() % 2 == 1 {
+                value.push(0);
+            }
+            info.extend_from_slice(&value);
+        }
+
+        let mut fmt = Vec::new();
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&8000u32.to_le_bytes());
+        fmt.extend_from_slice(&16000u32.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+
+        let mut chunks = b"WAVE".to_vec();
+        for (id, body) in [(b"fmt ", fmt), (b"LIST", info), (b"data", vec![0; 16000])] {
+            chunks.extend_from_slice(id);
+            chunks.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(&body);
+        }
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"RIFF").unwrap();
+        file.write_all(&(chunks.len() as u32).to_le_bytes()).unwrap();
+        file.write_all(&chunks).unwrap();
+        file
+    }
+
+    fn form(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn track_numbers_are_read_before_the_total() {
+        assert_eq!(leading_number("3/12"), Some(3));
+        assert_eq!(leading_number(" 7 "), Some(7));
+        assert_eq!(leading_number("04"), Some(4));
+    }
+
+    #[test]
+    fn track_numbers_that_are_not_numbers_are_ignored() {
+        assert_eq!(leading_number(""), None);
+        assert_eq!(leading_number("/12"), None);
+        assert_eq!(leading_number("A1"), None);
+        assert_eq!(leading_number("-1"), None);
+    }
+
+    #[test]
+    fn tags_are_read_from_wav_info_chunks() {
+        let file = tagged_wav(&[
+            (b"INAM", "Title"),
+            (b"IART", "Artist"),
+            (b"IPRD", "Album"),
+            (b"IPRT", "3/12"),
+            (b"ICRD", "2019-05-01"),
+            (b"IGNR", "Jazz")
+        ]);
+
+        assert_eq!(read_tags(file.path()), SongTags {
+            title: Some("Title".to_string()),
+            artists: vec!["Artist".to_string()],
+            album: Some("Album".to_string()),
+            track_number: Some(3),
+            year: Some(2019),
+            genre: Some("Jazz".to_string()),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn empty_tags_are_left_out() {
+        let file = tagged_wav(&[(b"INAM", " "), (b"IART", ""), (b"IPRT", "")]);
+        assert_eq!(read_tags(file.path()), SongTags::default());
+    }
+
+    #[test]
+    fn files_that_are_not_audio_have_no_tags() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"not audio").unwrap();
+        assert_eq!(read_tags(file.path()), SongTags::default());
+        assert_eq!(read_tags(Path::new("/nonexistent/upload")), SongTags::default());
+    }
+
+    #[test]
+    fn form_fields_override_embedded_tags() {
+        let embedded = SongTags {
+            title: Some("Embedded title".to_string()),
+            artists: vec!["Embedded artist".to_string(), "Featured artist".to_string()],
+            album: Some("Embedded album".to_string()),
+            track_number: Some(3),
+            ..Default::default()
+        };
+        let fields = form(&[("title", " Form title "), ("artist", ""), ("album", "   "), ("track_number", "5"), ("year", "2020")]);
+
+        assert_eq!(SongTags::from_form(&fields).unwrap().or(embedded), SongTags {
+            title: Some("Form title".to_string()),
+            artists: vec!["Embedded artist".to_string(), "Featured artist".to_string()],
+            album: Some("Embedded album".to_string()),
+            track_number: Some(5),
+            year: Some(2020),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn invalid_form_numbers_are_rejected() {
+        for (name, value) in [("track_number", "3/12"), ("disc_number", "-1"), ("year", "soon")] {
+            assert!(matches!(SongTags::from_form(&form(&[(name, value)])), Err(AppError::Validation(_))), "{}: {}", name, value);
+        }
+    }
+}
\ No newline at end of file
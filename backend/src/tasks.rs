@@ -0,0 +1,34 @@
+use std::time::Duration;
+use actix_web::rt;
+
+use crate::{
+    auth::purge_expired_sessions,
+    config::env_or,
+    db::{self, DbPool},
+    rate_limit::{purge_login_failures, LockoutPolicy}
+};
+
+/// Spawns a background task that periodically deletes expired sessions and forgets old failed logins.
+///
+/// The task runs every `SESSION_CLEANUP_INTERVAL_SECS` seconds (defaults to an hour, at least a second).
+pub fn spawn_session_cleanup(pool: DbPool) {
+    let seconds = env_or("SESSION_CLEANUP_INTERVAL_SECS", 3600_u64).max(1);
+    let lockout = LockoutPolicy::from_env();
+
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_secs(seconds));
+        loop {
+            interval.tick().await;
+            let result = db::run(&pool, purge_expired_sessions).await;
+            match result {
+                Ok(0) => {},
+                Ok(count) => println!("Purged {} expired sessions", count),
+                Err(err) => println!("Error purging expired sessions: {}", err)
+            }
+            let result = db::run(&pool, move |conn| purge_login_failures(conn, &lockout)).await;
+            if let Err(err) = result {
+                println!("Error purging failed logins: {}", err);
+            }
+        }
+    });
+}
\ No newline at end of file
@@ -0,0 +1,304 @@
+use std::io::Cursor;
+
+use backend::{
+    decoding::UploadDecoder,
+    encoding::{mp3_frame_header, wav_segment_to_mp3, wav_to_flac, Codec, SegmentFormat},
+    error::AppError,
+    samples::{frames_to_ms, segment_count, split_samples, PcmFormat, SampleConversion, SEGMENT_SECONDS}
+};
+use hound::{WavReader, WavWriter};
+
+const SAMPLE_RATE: u32 = 8000;
+
+/// A fixture WAV file of a sine wave at half of full scale, in the given format.
+fn fixture(format: PcmFormat, channels: u16, seconds: f64) -> Vec<u8> {
+    fixture_frames(format, channels, (seconds * SAMPLE_RATE as f64) as usize)
+}
+
+fn fixture_frames(format: PcmFormat, channels: u16, frames: usize) -> Vec<u8> {
+    let spec = format.spec(channels, SAMPLE_RATE);
+    let mut buffer = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut buffer, spec).unwrap();
+    for frame in 0..frames {
+        let value = (frame as f64 * 0.01).sin() * 0.5;
+        for _ in 0..channels {
+            match format {
+                PcmFormat::Int(bits) => writer.write_sample((value * (1i64 << (bits - 1)) as f64) as i32).unwrap(),
+                PcmFormat::Float => writer.write_sample(value as f32).unwrap()
+            }
+        }
+    }
+    writer.finalize().unwrap();
+    buffer.into_inner()
+}
+
+/// Splits an audio file held in memory into samples.
+fn get_all_samples(file: Vec<u8>, conversion: SampleConversion) -> Result<Vec<Vec<u8>>, AppError> {
+    let mut decoder = UploadDecoder::from_bytes(file)?;
+    let mut samples: Vec<Vec<u8>> = vec![];
+    split_samples(&mut decoder, conversion, |sample, _| {
+        samples.push(sample);
+        Ok(())
+    })?;
+    Ok(samples)
+}
+
+fn read_ints(wav: &[u8]) -> Vec<i32> {
+    let mut reader = WavReader::new(Cursor::new(wav)).unwrap();
+    reader.samples::<i32>().map(Result::unwrap).collect()
+}
+
+fn read_floats(wav: &[u8]) -> Vec<f32> {
+    let mut reader = WavReader::new(Cursor::new(wav)).unwrap();
+    reader.samples::<f32>().map(Result::unwrap).collect()
+}
+
+fn keep_format() -> SampleConversion {
+    SampleConversion { target: None, dither: false, segment_seconds: SEGMENT_SECONDS }
+}
+
+fn to_16_bit(dither: bool) -> SampleConversion {
+    SampleConversion { target: Some(PcmFormat::Int(16)), dither, segment_seconds: SEGMENT_SECONDS }
+}
+
+#[test]
+fn every_format_is_split_without_changing_it() {
+    let formats = [PcmFormat::Int(8), PcmFormat::Int(16), PcmFormat::Int(24), PcmFormat::Int(32), PcmFormat::Float];
+    for format in formats {
+        let file = fixture(format, 2, 12.0);
+        let segments = get_all_samples(file.clone(), keep_format()).unwrap();
+        assert_eq!(segments.len(), 2, "{:?}", format);
+
+        let first = WavReader::new(Cursor::new(&segments[0])).unwrap();
+        assert_eq!(PcmFormat::of(&first.spec()), format);
+        assert_eq!(first.duration(), SAMPLE_RATE * SEGMENT_SECONDS);
+
+        // The segments hold exactly the samples of the file
+        match format {
+            PcmFormat::Int(_) => {
+                let joined: Vec<i32> = segments.iter().flat_map(|segment| read_ints(segment)).collect();
+                assert_eq!(joined, read_ints(&file), "{:?}", format);
+            },
+            PcmFormat::Float => {
+                let joined: Vec<f32> = segments.iter().flat_map(|segment| read_floats(segment)).collect();
+                assert_eq!(joined, read_floats(&file));
+            }
+        }
+    }
+}
+
+#[test]
+fn flac_uploads_are_decoded_exactly() {
+    let file = fixture(PcmFormat::Int(16), 2, 25.0);
+    let flac = wav_to_flac(file.clone()).unwrap();
+    let decoder = UploadDecoder::from_bytes(flac.clone()).unwrap();
+    assert_eq!((decoder.channels(), decoder.sample_rate(), decoder.format()), (2, SAMPLE_RATE, PcmFormat::Int(16)));
+
+    let samples = get_all_samples(flac, keep_format()).unwrap();
+    let decoded: Vec<i32> = samples.iter().flat_map(|sample| read_ints(sample)).collect();
+    assert_eq!(decoded, read_ints(&file));
+}
+
+#[test]
+fn mp3_uploads_are_decoded() {
+    let file = fixture(PcmFormat::Int(16), 2, 5.0);
+    let frames = WavReader::new(Cursor::new(&file)).unwrap().duration() as usize;
+    let mp3 = encode_mp3(&[file]).concat();
+    let decoder = UploadDecoder::from_bytes(mp3.clone()).unwrap();
+    assert_eq!((decoder.channels(), decoder.sample_rate()), (2, SAMPLE_RATE));
+
+    // The encoder adds some silence at the start and pads out the last frame
+    let samples = get_all_samples(mp3, to_16_bit(false)).unwrap();
+    let decoded: usize = samples.iter().map(|sample| WavReader::new(Cursor::new(sample)).unwrap().duration() as usize).sum();
+    assert!(decoded >= frames && decoded < frames + 4 * 1152, "{} frames decoded from {}", decoded, frames);
+}
+
+#[test]
+fn high_bit_depths_are_rounded_to_16_bits() {
+    let file = fixture(PcmFormat::Int(24), 1, 3.0);
+    let segments = get_all_samples(file.clone(), to_16_bit(false)).unwrap();
+    assert_eq!(segments.len(), 1);
+
+    let segment = WavReader::new(Cursor::new(&segments[0])).unwrap();
+    assert_eq!(PcmFormat::of(&segment.spec()), PcmFormat::Int(16));
+    let expected: Vec<i32> = read_ints(&file).iter().map(|sample| (*sample as f64 / 256.0).round() as i32).collect();
+    assert_eq!(read_ints(&segments[0]), expected);
+}
+
+#[test]
+fn dither_stays_within_one_bit() {
+    let file = fixture(PcmFormat::Int(24), 1, 3.0);
+    let rounded = read_ints(&get_all_samples(file.clone(), to_16_bit(false)).unwrap()[0]);
+    let dithered = read_ints(&get_all_samples(file, to_16_bit(true)).unwrap()[0]);
+
+    assert_eq!(rounded.len(), dithered.len());
+    assert!(rounded.iter().zip(&dithered).all(|(rounded, dithered)| (rounded - dithered).abs() <= 1));
+    assert!(rounded != dithered, "Dither should change some samples");
+}
+
+#[test]
+fn float_samples_are_converted_and_clipped() {
+    let spec = PcmFormat::Float.spec(1, SAMPLE_RATE);
+    let mut buffer = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut buffer, spec).unwrap();
+    let samples = [0.0f32, 0.5, -0.5, 1.5, -1.5];
+    for sample in samples.iter().chain([0.0; SAMPLE_RATE as usize].iter()) {
+        writer.write_sample(*sample).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    let segments = get_all_samples(buffer.into_inner(), to_16_bit(false)).unwrap();
+    assert_eq!(read_ints(&segments[0])[..samples.len()], [0, 16384, -16384, 32767, -32768]);
+}
+
+#[test]
+fn samples_can_be_stored_in_other_formats() {
+    // 32-bit samples reduced to 24 bits, with dither staying within one bit of rounding
+    let file = fixture(PcmFormat::Int(32), 2, 3.0);
+    let to_24_bit = SampleConversion { target: Some(PcmFormat::Int(24)), dither: true, segment_seconds: SEGMENT_SECONDS };
+    let segments = get_all_samples(file.clone(), to_24_bit).unwrap();
+    assert_eq!(PcmFormat::of(&WavReader::new(Cursor::new(&segments[0])).unwrap().spec()), PcmFormat::Int(24));
+    let expected = read_ints(&file).into_iter().map(|sample| (sample as f64 / 256.0).round() as i32);
+    assert!(read_ints(&segments[0]).into_iter().zip(expected).all(|(sample, expected)| (sample - expected).abs() <= 1));
+
+    // 16-bit samples widened to floats exactly
+    let file = fixture(PcmFormat::Int(16), 2, 3.0);
+    let to_float = SampleConversion { target: Some(PcmFormat::Float), dither: true, segment_seconds: SEGMENT_SECONDS };
+    let segments = get_all_samples(file.clone(), to_float).unwrap();
+    let expected: Vec<f32> = read_ints(&file).into_iter().map(|sample| sample as f32 / 32768.0).collect();
+    assert_eq!(read_floats(&segments[0]), expected);
+}
+
+#[test]
+fn renditions_are_encoded_from_24_bit_samples() {
+    let file = fixture(PcmFormat::Int(24), 2, 25.0);
+    let samples = get_all_samples(file.clone(), keep_format()).unwrap();
+
+    // FLAC keeps all 24 bits
+    for sample in &samples {
+        let flac = wav_to_flac(sample.clone()).unwrap();
+        let decoded = get_all_samples(flac, keep_format()).unwrap();
+        assert_eq!(read_ints(&decoded[0]), read_ints(sample));
+    }
+
+    // MP3 samples are cut at the same frames as from 16-bit samples
+    let encoded = encode_mp3(&samples);
+    let from_16_bit = encode_mp3(&get_all_samples(file, to_16_bit(false)).unwrap());
+    let frames: Vec<usize> = encoded.iter().map(|sample| mp3_frame_count(sample)).collect();
+    assert_eq!(frames, from_16_bit.iter().map(|sample| mp3_frame_count(sample)).collect::<Vec<usize>>());
+}
+
+#[test]
+fn lower_bit_depths_are_widened_exactly() {
+    let file = fixture(PcmFormat::Int(8), 1, 1.0);
+    // There is no precision to lose, so dither must not be added
+    let segments = get_all_samples(file.clone(), to_16_bit(true)).unwrap();
+    let expected: Vec<i32> = read_ints(&file).iter().map(|sample| sample * 256).collect();
+    assert_eq!(read_ints(&segments[0]), expected);
+}
+
+#[test]
+fn segments_are_the_configured_length() {
+    let file = fixture(PcmFormat::Int(16), 2, 9.5);
+    let conversion = SampleConversion { segment_seconds: 4, ..keep_format() };
+    let segments = get_all_samples(file, conversion).unwrap();
+    assert_eq!(segment_lengths(&segments), [SAMPLE_RATE * 4, SAMPLE_RATE * 4, SAMPLE_RATE * 3 / 2]);
+}
+
+fn segment_lengths(segments: &[Vec<u8>]) -> Vec<u32> {
+    segments.iter().map(|segment| WavReader::new(Cursor::new(segment)).unwrap().duration()).collect()
+}
+
+#[test]
+fn no_audio_is_lost_at_segment_boundaries() {
+    let full = SAMPLE_RATE * SEGMENT_SECONDS;
+    let cases: [(u32, &[u32]); 6] = [
+        (1, &[1]),
+        (SAMPLE_RATE / 2, &[SAMPLE_RATE / 2]),
+        (full - 1, &[full - 1]),
+        (full, &[full]),
+        (full + 1, &[full, 1]),
+        (full * 2, &[full, full])
+    ];
+    for (frames, expected) in cases {
+        let file = fixture_frames(PcmFormat::Int(16), 2, frames as usize);
+        let segments = get_all_samples(file, keep_format()).unwrap();
+        assert_eq!(segment_lengths(&segments), expected, "{} frames", frames);
+        assert_eq!(segment_count(frames as u64, SAMPLE_RATE, SEGMENT_SECONDS), expected.len() as u64, "{} frames", frames);
+    }
+}
+
+#[test]
+fn durations_are_rounded_up_to_the_millisecond() {
+    assert_eq!(frames_to_ms(0, 44100), 0);
+    assert_eq!(frames_to_ms(1, 44100), 1);
+    assert_eq!(frames_to_ms(441, 44100), 10);
+    assert_eq!(frames_to_ms(442, 44100), 11);
+    assert_eq!(frames_to_ms(44100 * 25, 44100), 25_000);
+    assert_eq!(frames_to_ms(44100 * 25 + 1, 44100), 25_001);
+    assert_eq!(frames_to_ms(1_125_888, 48000), 23_456);
+    // Long songs don't overflow
+    assert_eq!(frames_to_ms(192_000 * 3600 * 24, 192_000), 86_400_000);
+}
+
+#[test]
+fn segments_are_counted_with_the_shorter_last_one() {
+    assert_eq!(segment_count(0, 44100, 10), 0);
+    assert_eq!(segment_count(441_000, 44100, 10), 1);
+    assert_eq!(segment_count(441_001, 44100, 10), 2);
+    assert_eq!(segment_count(1_125_888, 48000, 6), 4);
+}
+
+/// Encodes the samples of a song to MP3 like they are when it is added.
+fn encode_mp3(samples: &[Vec<u8>]) -> Vec<Vec<u8>> {
+    let format = SegmentFormat { codec: Codec::Mp3, bitrate_kbps: 32 };
+    let mut encoder = format.song_encoder(2, SAMPLE_RATE).unwrap();
+    let mut encoded = Vec::new();
+    for sample in samples {
+        encoded.extend(encoder.push(sample.clone()).unwrap());
+    }
+    encoded.extend(encoder.finish().unwrap());
+    encoded
+}
+
+fn mp3_frame_count(mut mp3: &[u8]) -> usize {
+    let mut frames = 0;
+    while let Some(header) = mp3_frame_header(mp3) {
+        mp3 = &mp3[header.len..];
+        frames += 1;
+    }
+    assert!(mp3.is_empty(), "MP3 ends part way through a frame");
+    frames
+}
+
+#[test]
+fn mp3_samples_are_cut_from_one_stream() {
+    let file = fixture(PcmFormat::Int(16), 2, 25.0);
+    let samples = get_all_samples(file.clone(), to_16_bit(false)).unwrap();
+    let encoded = encode_mp3(&samples);
+    assert_eq!(encoded.len(), samples.len());
+
+    // Put back together, the samples are exactly the song encoded in one go
+    let whole = encode_mp3(&[file]);
+    assert_eq!(encoded.concat(), whole.concat());
+    for sample in &encoded {
+        assert!(mp3_frame_count(sample) > 0);
+    }
+}
+
+#[test]
+fn mp3_samples_encoded_on_their_own_join_up() {
+    let file = fixture_frames(PcmFormat::Int(16), 2, (SAMPLE_RATE * SEGMENT_SECONDS * 2 + 100) as usize);
+    let samples = get_all_samples(file, to_16_bit(false)).unwrap();
+    let encoded = encode_mp3(&samples);
+
+    // Each sample is cut at the same frames as when the whole song is encoded, however it was encoded
+    let mut start = 0;
+    for (n, sample) in samples.iter().enumerate() {
+        let previous = n.checked_sub(1).map(|previous| samples[previous].clone());
+        let next = samples.get(n + 1).cloned();
+        let on_its_own = wav_segment_to_mp3(previous, sample.clone(), next, start, 32).unwrap();
+        assert_eq!(mp3_frame_count(&on_its_own), mp3_frame_count(&encoded[n]), "sample {}", n);
+        start += WavReader::new(Cursor::new(sample)).unwrap().duration() as u64;
+    }
+}
\ No newline at end of file
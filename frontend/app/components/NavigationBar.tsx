@@ -1,5 +1,3 @@
-import Cookies from "js-cookie";
-
 export default function NavigationBar({ server_url }: { server_url: string }) {
     return (
     <div id="navbar" className="flex items-center justify-between w-full bg-neutral-900 p-3">
@@ -12,14 +10,8 @@ export default function NavigationBar({ server_url }: { server_url: string }) {
           <button className="btn btn-primary btn-md" onClick={async () => {
             await fetch(server_url + "/logout", {
               method: "POST",
-              headers: {
-                "Content-Type": "application/json",
-              },
-              body: JSON.stringify({
-                session_id: Cookies.get("session_id"),
-              })
+              credentials: "include",
             });
-            Cookies.remove("session_id");
             window.location.href = "/";
           }}>Logout</button>
         </div>
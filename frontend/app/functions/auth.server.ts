@@ -1,3 +1,5 @@
+import { User } from "~/types";
+
 function parseCookieHeader(cookieHeader: string) {
     const splitCookies = cookieHeader.split(";");
     const keyValuePars = splitCookies.map(pair => pair.split("="));
@@ -17,11 +19,8 @@ export async function hasValidSession(request: Request) {
         const response = await fetch(process.env.SERVER_URL_FROM_SERVER + "/validate_session", {
           method: "POST",
           headers: {
-            "Content-Type": "application/json",
+            "Authorization": `Bearer ${session}`,
           },
-          body: JSON.stringify({
-            session_id: session,
-          }),
         });
         const response_body = await response.text();
         if (response_body == "true") {
@@ -32,16 +31,22 @@ export async function hasValidSession(request: Request) {
   return false;
 }
 
-export async function getUser(request: Request): Promise<{ id: string, username: string, permissions: string } | null> {
+export async function getUser(request: Request): Promise<User | null> {
   const cookieHeader = request.headers.get("Cookie");
   if (cookieHeader !== null) {
     const cookies = parseCookieHeader(cookieHeader);
     const session = cookies["session_id"];
     if (session) {
-      const response = await fetch(process.env.SERVER_URL_FROM_SERVER + "/user/" + session, {
-        method: "GET"
+      const response = await fetch(process.env.SERVER_URL_FROM_SERVER + "/me", {
+        method: "GET",
+        headers: {
+          "Authorization": `Bearer ${session}`,
+        },
       });
-      const response_body = await response.json() as { id: string, username: string, permissions: string };
+      if (response.status !== 200) {
+        return null;
+      }
+      const response_body = await response.json() as User;
       return response_body;
     }
   }
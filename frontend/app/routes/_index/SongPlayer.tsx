@@ -1,7 +1,7 @@
-import { useEffect, useState } from "react";
+import { useEffect, useRef, useState } from "react";
 import { FaBackwardStep, FaForwardStep, FaPause, FaPlay, FaVolumeHigh } from "react-icons/fa6";
 import pako from 'pako';
-import { Song } from "~/types";
+import { SongInfo } from "~/types";
 import { HiOutlineQueueList } from "react-icons/hi2";
 
 function formatTime(seconds: number): string {
@@ -19,7 +19,7 @@ export default function SongPlayer({
   cloudFrontUrl
 }: {
   server_url: string,
-  song_info: Song,
+  song_info: SongInfo,
   currentSong: string,
   cloudFrontUrl: string
 }) {
@@ -32,6 +32,8 @@ export default function SongPlayer({
     const [loadedSamples, setLoadedSamples] = useState(0); // 0 means no samples have been loaded
     const [songInfo, setSongInfo] = useState(song_info);
     const [songImage, setSongImage] = useState(`${cloudFrontUrl}/${currentSong}/${currentSong}.png`);
+    // When the samples loaded so far end, which is where the next one is played
+    const nextStartTime = useRef(0);
 
     async function GetAudio(sample_number: number = 0) {
       const response = await fetch(server_url + "/sample_compressed/" + encodeURI(currentSong) + "/" + sample_number);
@@ -39,6 +41,11 @@ export default function SongPlayer({
           return null;
       }
       const encodedAudioBuffer = await response.arrayBuffer();
+      // Encoded samples (e.g. MP3) can be decoded as they are, WAV samples are compressed with zlib
+      if (response.headers.get("Content-Type") !== "application/zlib") {
+        setLoadedSamples(prev => prev + 1);
+        return encodedAudioBuffer;
+      }
       const inflator = new pako.Inflate();
       inflator.push(encodedAudioBuffer);
       if (inflator.err) {
@@ -52,12 +59,22 @@ export default function SongPlayer({
 
     async function GetSongInfo() {
       const response = await fetch(server_url + "/song_info/" + encodeURI(currentSong));
-      const songInfo = await response.json() as Song;
+      const songInfo = await response.json() as SongInfo;
       // Update the song image once the song info is loaded
       setSongImage(`${cloudFrontUrl}/${currentSong}/${currentSong}.png`);
       setSongInfo(songInfo);
     }
 
+    // When a sample starts in the song in seconds, or when the song ends for samples past the last one
+    function sampleStart(sampleNumber: number): number {
+      const segments = songInfo.segments;
+      if (sampleNumber < segments.length) {
+          return segments[sampleNumber].start_ms / 1000;
+      }
+      const last = segments[segments.length - 1];
+      return last ? (last.start_ms + last.duration_ms) / 1000 : 0;
+    }
+
     async function decodeAudioChunk(arrayBuffer: ArrayBuffer) {
       if (audioContext === null)
           return;
@@ -109,7 +126,10 @@ export default function SongPlayer({
       }
       const decodedAudioBuffer = await decodeAudioChunk(audioBuffer);
       if (decodedAudioBuffer) {
-          playAudioChunk(decodedAudioBuffer, sampleNumber * 10);
+          // MP3 samples are cut between frames, so they can be a little longer or shorter than their part of the song,
+          // and are played straight after the one before rather than where they start in the song
+          playAudioChunk(decodedAudioBuffer, nextStartTime.current);
+          nextStartTime.current += decodedAudioBuffer.duration;
       }
     }
 
@@ -119,6 +139,7 @@ export default function SongPlayer({
       setGainNode(null);
       setCurrentTime(0);
       setCurrentSample(-1);
+      nextStartTime.current = 0;
       setLoadedSamples(0);
       setPlaying(false);
     }
@@ -139,7 +160,7 @@ export default function SongPlayer({
       const interval = setInterval(async () => {
           if (playing && audioContext) {
             const currentTime = audioContext.currentTime;
-            if (currentTime >= loadedSamples * 10) {
+            if (currentTime >= sampleStart(loadedSamples)) {
                 // The next sample is not loaded yet, so suspend the audio context
                 audioContext.suspend();
             }
@@ -147,11 +168,11 @@ export default function SongPlayer({
                 audioContext.resume();
             }
             // Fetch next sample 5 seconds before it's needed
-            if (currentTime >= (currentSample + 1) * 10 - 5) {
+            if (currentTime >= sampleStart(currentSample + 1) - 5) {
                 setCurrentSample(prev => prev + 1);
                 await nextSample(currentSample + 1);
             }
-            if (currentTime >= songInfo.duration) {
+            if (currentTime >= songInfo.duration_ms / 1000) {
                 stopAudio();
             } else {
                 setCurrentTime(currentTime);
@@ -194,8 +215,8 @@ export default function SongPlayer({
           </div>
           <div className="flex w-full items-center gap-2">
             <span id="current-song-time" className="text-white text-md w-12">{formatTime(currentTime)}</span>
-            <progress className="progress w-full" value={currentTime} max={songInfo.duration}></progress>
-            <span id="song-duration" className="text-white text-md">{formatTime(songInfo.duration)}</span>
+            <progress className="progress w-full" value={currentTime} max={songInfo.duration_ms / 1000}></progress>
+            <span id="song-duration" className="text-white text-md">{formatTime(songInfo.duration_ms / 1000)}</span>
           </div>
         </div>
         <div id="right-section" className="flex w-1/4 gap-4 items-center">
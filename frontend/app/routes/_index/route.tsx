@@ -9,7 +9,7 @@ import MainPageContent from "./MainPageContent";
 import MainPageContentSkeleton from "./MainPageContentSkeleton";
 import SongPlayerSkeleton from "./SongPlayerSkeleton";
 import NavigationBar from "~/components/NavigationBar";
-import { Song } from "~/types";
+import { SongInfo } from "~/types";
 
 export const meta: MetaFunction = () => {
   return [
@@ -20,7 +20,7 @@ export const meta: MetaFunction = () => {
 
 async function getSongInfo(server_url: string, song_id: string) {
   const response = await fetch(server_url + "/song_info/" + encodeURI(song_id));
-  const songInfo = await response.json() as Song;
+  const songInfo = await response.json() as SongInfo;
   return songInfo;
 }
 
@@ -10,7 +10,7 @@ export async function loader({ request }: LoaderFunctionArgs) {
     if (user === null) {
         return redirect("/login");
     }
-    if(user.permissions !== "admin") {
+    if(!user.capabilities.includes("delete_songs")) {
         return redirect("/login");
     }
     const server_url = process.env.SERVER_URL_FROM_SERVER;
@@ -49,7 +49,8 @@ export default function Admin() {
                                     setDisabled(true);
                                     const response = await fetch(`${server_url}/song/${current.id}`,
                                         {
-                                            method: "DELETE"
+                                            method: "DELETE",
+                                            credentials: "include"
                                         }
                                     );
                                     if (response.status === 200) {
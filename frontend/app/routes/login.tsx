@@ -1,5 +1,4 @@
 import { useState } from "react";
-import Cookies from 'js-cookie';
 import { redirect, useLoaderData } from "@remix-run/react";
 import { LoaderFunctionArgs } from "@remix-run/node";
 import { hasValidSession } from "../functions/auth.server";
@@ -34,6 +33,7 @@ export default function Login() {
                 <button className="btn btn-secondary btn-lg btn-wide" onClick={() => {
                     fetch(environment.server_url + "/login", {
                         method: "POST",
+                        credentials: "include",
                         headers: {
                             "Content-Type": "application/json",
                         },
@@ -41,15 +41,13 @@ export default function Login() {
                             username: username,
                             password: password,
                         }),
-                    }).then(response => response.json()).then((data: { session_id: string, error: string }) => {
+                    }).then(response => response.json()).then((data: { error: string }) => {
                         if (password.length == 0 || username.length == 0) {
                             setError("Username and password cannot be empty!");
                             return;
                         }
                         if (data.error == "")
                         {
-                            // Setting the session cookie
-                            Cookies.set("session_id", data.session_id, { expires: 30});
                             // Redirecting to the home page
                             window.location.href = "/";
                         }
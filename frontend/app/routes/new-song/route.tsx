@@ -9,7 +9,7 @@ export async function loader({ request }: LoaderFunctionArgs) {
     if (user === null) {
         return redirect("/login");
     }
-    if(user.permissions !== "admin") {
+    if(!user.capabilities.includes("upload_songs")) {
         return redirect("/login");
     }
     const server_url = process.env.SERVER_URL;
@@ -29,10 +29,11 @@ export default function NewSong() {
     const [submitDisabled, setSubmitDisabled] = useState(false);
 
     async function UploadFile() {
-        if (songTitle === "" || songArtist === "" || songAlbum === "" || songFiles === null || songImage === null) {
+        if (songFiles === null || songImage === null) {
             return;
         }
         setSubmitDisabled(true);
+        // Details left empty are read from the tags in the song file
         const formData = new FormData();
         formData.append("title", songTitle);
         formData.append("artist", songArtist);
@@ -41,12 +42,13 @@ export default function NewSong() {
         formData.append("image", songImage[0]);
         const response = await fetch(server_url + "/song", {
             method: "POST",
+            credentials: "include",
             body: formData
         });
-        const body = await response.text();
-        if (response.status !== 200) {
+        if (!response.ok) {
+            const body = await response.json() as { error: string, code: string };
             setSubmitDisabled(false);
-            alert(body);
+            alert(body.error);
             return;
         }
         window.location.href = "/admin";
@@ -59,18 +61,18 @@ export default function NewSong() {
             <div className="flex flex-col gap-4 items-center justify-center w-full flex-grow">
                 <h1 className="text-white text-4xl mx-auto font-bold">New Song</h1>
                 <div id="new-song-form" className="flex flex-col gap-4 items-center w-full max-w-2xl mx-auto">
-                    <input disabled={submitDisabled} value={songTitle} onChange={(e) => setSongTitle(e.target.value)} type="text" className="input input-bordered w-full" placeholder="Song Title" />
-                    <input disabled={submitDisabled} value={songArtist} onChange={(e) => setSongArtist(e.target.value)} type="text" className="input input-bordered w-full" placeholder="Song Artist" />
-                    <input disabled={submitDisabled} value={songAlbum} onChange={(e) => setSongAlbum(e.target.value)} type="text" className="input input-bordered w-full" placeholder="Song Album" />
+                    <input disabled={submitDisabled} value={songTitle} onChange={(e) => setSongTitle(e.target.value)} type="text" className="input input-bordered w-full" placeholder="Song Title (optional)" />
+                    <input disabled={submitDisabled} value={songArtist} onChange={(e) => setSongArtist(e.target.value)} type="text" className="input input-bordered w-full" placeholder="Song Artist (optional)" />
+                    <input disabled={submitDisabled} value={songAlbum} onChange={(e) => setSongAlbum(e.target.value)} type="text" className="input input-bordered w-full" placeholder="Song Album (optional)" />
                     <div className="flex flex-col gap-0.5 w-full">
                         <span className="text-lg font-semibold">Album Cover:</span>
                         <input disabled={submitDisabled} onChange={(e) => setSongImage(e.target.files)} type="file" className="p-4 input input-bordered w-full h-full" placeholder="Song Image" />
                     </div>
                     <div className="flex flex-col gap-0.5 w-full">
                         <span className="text-lg font-semibold">Song File:</span>
-                        <input accept="audio/wav, audio/mpeg" disabled={submitDisabled} onChange={(e) => setSongFiles(e.target.files)} type="file" className="p-4 input input-bordered w-full h-full" placeholder="Song File" />
+                        <input accept="audio/*" disabled={submitDisabled} onChange={(e) => setSongFiles(e.target.files)} type="file" className="p-4 input input-bordered w-full h-full" placeholder="Song File" />
                     </div>
-                    <button disabled={songTitle === "" || songArtist === "" || songAlbum === "" || songFiles === null || submitDisabled} onClick={() => UploadFile()} className="btn btn-secondary btn-lg btn-wide">Add Song</button>
+                    <button disabled={songFiles === null || songImage === null || submitDisabled} onClick={() => UploadFile()} className="btn btn-secondary btn-lg btn-wide">Add Song</button>
                 </div>
             </div>
         </div>
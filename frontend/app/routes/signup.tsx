@@ -2,7 +2,6 @@ import { LoaderFunctionArgs } from "@remix-run/node";
 import { redirect, useLoaderData } from "@remix-run/react";
 import { hasValidSession } from "../functions/auth.server";
 import { useState } from "react";
-import Cookies from "js-cookie";
 
 export async function loader({ request }: LoaderFunctionArgs) {
     const isAuthenticated = await hasValidSession(request);
@@ -51,6 +50,7 @@ export default function Signup() {
                     }
                     fetch(environment.server_url + "/signup", {
                         method: "POST",
+                        credentials: "include",
                         headers: {
                             "Content-Type": "application/json",
                         },
@@ -58,11 +58,9 @@ export default function Signup() {
                             username: username,
                             password: password,
                         }),
-                    }).then(response => response.json()).then((data: { session_id: string, error: string }) => {
+                    }).then(response => response.json()).then((data: { error: string }) => {
                         if (data.error == "")
                         {
-                            // Setting the session cookie
-                            Cookies.set("session_id", data.session_id);
                             // Redirecting to the home page
                             window.location.href = "/";
                         }
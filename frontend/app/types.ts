@@ -3,6 +3,66 @@ export type Song = {
     title: string,
     artist: string,
     album: string,
-    duration: number,
-    num_samples: number
+    duration_ms: number,
+    num_samples: number,
+    lossless: boolean,
+    status: "staged" | "ready" | "deleting",
+    sample_rate: number | null,
+    album_artist: string | null,
+    track_number: number | null,
+    disc_number: number | null,
+    year: number | null,
+    genre: string | null,
+    isrc: string | null
+}
+
+export type ManifestSegment = {
+    segment_number: number,
+    start_ms: number,
+    duration_ms: number,
+    renditions: {
+        rendition: string,
+        codec: "wav" | "mp3" | "flac",
+        byte_length: number | null,
+        checksum: string | null
+    }[]
+}
+
+export type SongInfo = Song & {
+    exact_segments: boolean,
+    segments: ManifestSegment[]
+}
+
+export type SeekPosition = {
+    segment_number: number,
+    segment_start_ms: number,
+    segment_duration_ms: number,
+    offset_ms: number,
+    offset_frames: number | null
+}
+
+export type Rendition = {
+    id: string,
+    song_id: string,
+    name: string,
+    codec: "wav" | "mp3" | "flac",
+    bitrate_kbps: number | null
+}
+
+export type IngestJob = {
+    id: string,
+    song_id: string,
+    status: "queued" | "processing" | "failed" | "ready",
+    progress: number,
+    attempts: number,
+    error: string | null,
+    created_at: string,
+    updated_at: string
+}
+
+export type User = {
+    id: string,
+    username: string,
+    role: "listener" | "uploader" | "admin",
+    capabilities: string[]
 }
\ No newline at end of file